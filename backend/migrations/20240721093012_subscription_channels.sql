@@ -0,0 +1,2 @@
+ALTER TABLE subscriptions ADD COLUMN channel INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE subscriptions ADD COLUMN token TEXT;
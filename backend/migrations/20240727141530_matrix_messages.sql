@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS matrix_messages
+(
+    endpoint    TEXT NOT NULL,
+    game_id     INTEGER NOT NULL,
+    event_id    TEXT NOT NULL,
+    PRIMARY KEY (endpoint, game_id)
+);
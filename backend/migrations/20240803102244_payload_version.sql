@@ -0,0 +1,2 @@
+-- 0 is the original plain text payload, anything later is the versioned JSON payload
+ALTER TABLE subscriptions ADD COLUMN payload_version INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,26 @@
+-- Keep what each alert said, so deliveries can be (re)sent after the game has moved on
+ALTER TABLE alerts ADD COLUMN home_team INTEGER;
+ALTER TABLE alerts ADD COLUMN away_team INTEGER;
+ALTER TABLE alerts ADD COLUMN home_score INTEGER;
+ALTER TABLE alerts ADD COLUMN away_score INTEGER;
+ALTER TABLE alerts ADD COLUMN timestr TEXT;
+ALTER TABLE alerts ADD COLUMN created_at INTEGER;
+
+-- One row per alert per subscription, times are unix seconds
+CREATE TABLE IF NOT EXISTS outbox
+(
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    alert_id        INTEGER NOT NULL,
+    endpoint        TEXT NOT NULL,
+    status          INTEGER NOT NULL DEFAULT 0,
+    attempts        INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at INTEGER NOT NULL,
+    expires_at      INTEGER NOT NULL,
+    claim           INTEGER,
+    last_error      TEXT,
+    created_at      INTEGER NOT NULL,
+    completed_at    INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS outbox_due ON outbox (status, next_attempt_at);
+CREATE INDEX IF NOT EXISTS outbox_claim ON outbox (claim);
@@ -0,0 +1,7 @@
+-- Alerts are claimed by inserting them, so each game can only have one of each
+DELETE FROM alerts
+WHERE rowid NOT IN (SELECT MIN(rowid) FROM alerts GROUP BY id, notification);
+
+CREATE UNIQUE INDEX IF NOT EXISTS alerts_game_notification ON alerts (id, notification);
+
+CREATE UNIQUE INDEX IF NOT EXISTS outbox_alert_endpoint ON outbox (alert_id, endpoint);
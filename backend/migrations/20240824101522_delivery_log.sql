@@ -0,0 +1,16 @@
+-- Every attempt at sending an alert, kept so subscribers can see what they were sent
+CREATE TABLE IF NOT EXISTS delivery_log
+(
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    endpoint     TEXT NOT NULL,
+    game_id      INTEGER,
+    notification INTEGER,
+    title        TEXT NOT NULL,
+    body         TEXT NOT NULL,
+    attempted_at INTEGER NOT NULL,
+    status_code  INTEGER,
+    latency_ms   INTEGER NOT NULL,
+    error        TEXT
+);
+
+CREATE INDEX IF NOT EXISTS delivery_log_endpoint ON delivery_log (endpoint, attempted_at);
@@ -0,0 +1,4 @@
+-- IANA timezone name, e.g. Australia/Sydney, and quiet hours as HH:MM local time
+ALTER TABLE subscriptions ADD COLUMN timezone TEXT;
+ALTER TABLE subscriptions ADD COLUMN quiet_hours_start TEXT;
+ALTER TABLE subscriptions ADD COLUMN quiet_hours_end TEXT;
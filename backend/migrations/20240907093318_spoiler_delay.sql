@@ -0,0 +1,4 @@
+-- Seconds to hold alerts for after the real event, for people watching on delay
+ALTER TABLE subscriptions ADD COLUMN spoiler_delay INTEGER;
+-- Hold all alerts until the subscriber releases them
+ALTER TABLE subscriptions ADD COLUMN hold_alerts INTEGER NOT NULL DEFAULT 0;
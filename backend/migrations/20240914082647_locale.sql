@@ -0,0 +1,3 @@
+-- Language and style of alert text, see `templates`
+ALTER TABLE subscriptions ADD COLUMN locale INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE subscriptions ADD COLUMN style INTEGER NOT NULL DEFAULT 0;
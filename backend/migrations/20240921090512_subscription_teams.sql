@@ -0,0 +1,12 @@
+-- Teams a subscription follows, a subscription without any gets alerts for every game
+CREATE TABLE IF NOT EXISTS subscription_teams
+(
+    endpoint    TEXT NOT NULL,
+    team        INTEGER NOT NULL,
+    PRIMARY KEY (endpoint, team)
+);
+
+INSERT INTO subscription_teams (endpoint, team)
+SELECT endpoint, team FROM subscriptions WHERE team IS NOT NULL;
+
+ALTER TABLE subscriptions DROP COLUMN team;
@@ -0,0 +1,13 @@
+-- Kinds of alert wanted, one bit per notification kind: end of Q1 (1), Q2 (2), Q3 (4), end of
+-- game (8) and close game (16). Quarter scores used to include the end of the game, now it's
+-- only sent to those who asked for final scores.
+ALTER TABLE subscriptions ADD COLUMN notifications INTEGER NOT NULL DEFAULT 0;
+
+UPDATE subscriptions
+SET notifications = IIF(quarter_scores, 1 | 2 | 4, 0)
+                  | IIF(final_scores, 8, 0)
+                  | IIF(close_games, 16, 0);
+
+ALTER TABLE subscriptions DROP COLUMN close_games;
+ALTER TABLE subscriptions DROP COLUMN final_scores;
+ALTER TABLE subscriptions DROP COLUMN quarter_scores;
@@ -0,0 +1,3 @@
+-- Kinds of alert wanted for games without any of the subscription's teams, `notifications` is
+-- used for games with them. Nothing by default, which is what those games used to get.
+ALTER TABLE subscriptions ADD COLUMN other_notifications INTEGER NOT NULL DEFAULT 0;
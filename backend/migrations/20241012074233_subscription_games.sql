@@ -0,0 +1,11 @@
+-- Single games a subscription follows, with the kinds of alert wanted for them. Removed once
+-- the game's full time alert is recorded.
+CREATE TABLE IF NOT EXISTS subscription_games
+(
+    endpoint        TEXT NOT NULL,
+    game_id         INTEGER NOT NULL,
+    notifications   INTEGER NOT NULL,
+    PRIMARY KEY (endpoint, game_id)
+);
+
+CREATE INDEX IF NOT EXISTS subscription_games_game ON subscription_games (game_id);
@@ -0,0 +1,4 @@
+-- SHA-256 of the secret token issued when subscribing, needed to read or change the
+-- subscription. NULL for subscriptions from before tokens, which can prove ownership with their
+-- web push `auth` key instead.
+ALTER TABLE subscriptions ADD COLUMN management_token_hash TEXT;
@@ -0,0 +1,3 @@
+-- Browsers can move a push subscription to a new endpoint. The old row is kept, pointing at the
+-- endpoint that replaced it.
+ALTER TABLE subscriptions ADD COLUMN superseded_by TEXT;
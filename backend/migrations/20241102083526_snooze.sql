@@ -0,0 +1,5 @@
+-- Snoozing stops alerts until a time (unix seconds), or until a round of a year starts. A year
+-- without a round snoozes until that year's finals.
+ALTER TABLE subscriptions ADD COLUMN snoozed_until INTEGER;
+ALTER TABLE subscriptions ADD COLUMN snoozed_year INTEGER;
+ALTER TABLE subscriptions ADD COLUMN snoozed_round INTEGER;
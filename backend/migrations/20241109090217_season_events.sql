@@ -0,0 +1,8 @@
+-- Pushes to every subscriber at either end of a season, recorded so each only goes out once
+CREATE TABLE IF NOT EXISTS season_events
+(
+    year    INTEGER NOT NULL,
+    kind    INTEGER NOT NULL,
+    sent_at INTEGER NOT NULL,
+    PRIMARY KEY (year, kind)
+);
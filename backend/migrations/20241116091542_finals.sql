@@ -0,0 +1,7 @@
+-- Which final a game is, as Squiggle numbers them, 0 for the home and away season
+ALTER TABLE games ADD COLUMN is_final INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE games ADD COLUMN is_grand_final BOOLEAN NOT NULL DEFAULT 0;
+
+-- Which games a subscription wants alerts for on top of the ones it follows: every game (0),
+-- just finals (1) or just the Grand Final (2)
+ALTER TABLE subscriptions ADD COLUMN scope INTEGER NOT NULL DEFAULT 0;
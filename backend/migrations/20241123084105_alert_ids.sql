@@ -0,0 +1,24 @@
+-- The outbox points at alerts by rowid, which VACUUM is free to renumber unless it's an
+-- INTEGER PRIMARY KEY, so alerts get one that keeps the rowids they already have
+CREATE TABLE alerts_new
+(
+    alert_id     INTEGER PRIMARY KEY,
+    id           INTEGER NOT NULL,
+    notification INTEGER NOT NULL,
+    home_team    INTEGER,
+    away_team    INTEGER,
+    home_score   INTEGER,
+    away_score   INTEGER,
+    timestr      TEXT,
+    created_at   INTEGER
+);
+
+INSERT INTO alerts_new (alert_id, id, notification, home_team, away_team, home_score,
+                        away_score, timestr, created_at)
+SELECT rowid, id, notification, home_team, away_team, home_score, away_score, timestr, created_at
+FROM alerts;
+
+DROP TABLE alerts;
+ALTER TABLE alerts_new RENAME TO alerts;
+
+CREATE UNIQUE INDEX IF NOT EXISTS alerts_game_notification ON alerts (id, notification);
@@ -0,0 +1,8 @@
+-- One-time codes sent to subscriptions from before management tokens, which have nothing else
+-- to prove ownership with
+CREATE TABLE IF NOT EXISTS subscription_claims
+(
+    endpoint   TEXT    NOT NULL PRIMARY KEY,
+    code_hash  TEXT    NOT NULL,
+    expires_at INTEGER NOT NULL
+);
@@ -0,0 +1,10 @@
+-- Subscribers a season push has been sent to, so a push that's interrupted or held up by a
+-- push service carries on from where it got to
+CREATE TABLE IF NOT EXISTS season_event_recipients
+(
+    year     INTEGER NOT NULL,
+    kind     INTEGER NOT NULL,
+    endpoint TEXT    NOT NULL,
+    sent_at  INTEGER NOT NULL,
+    PRIMARY KEY (year, kind, endpoint)
+);
@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::types::{GameId, Team, TimeStr};
 
@@ -10,7 +10,7 @@ pub enum Side {
     Home,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub struct Score {
     #[serde(rename = "hscore")]
     pub home_score: u16,
@@ -18,18 +18,20 @@ pub struct Score {
     pub away_score: u16,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct ScoreEvent {
     #[serde(rename = "gameid")]
     pub game_id: GameId,
     #[serde(rename = "type")]
     pub score_type: String,
+    /// The team that scored
+    pub team: Option<Team>,
     pub complete: u8,
     pub score: Score,
     pub timestr: TimeStr,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct GameEvent {
     pub id: GameId,
     pub round: u16,
@@ -54,28 +56,28 @@ pub struct GameEvent {
     pub timestr: TimeStr,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct TimeStrEvent {
     #[serde(rename = "gameid")]
     pub game_id: GameId,
     pub timestr: TimeStr,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct CompleteEvent {
     #[serde(rename = "gameid")]
     pub game_id: GameId,
     pub complete: u8,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct WinnerEvent {
     #[serde(rename = "gameid")]
     pub game_id: GameId,
     pub winner: Team,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum Event {
     /// Sent when a score occurs
@@ -139,6 +141,7 @@ pub mod test {
 
         assert_eq!(score.game_id, 8706);
         assert_eq!(score.score_type, "behind");
+        assert_eq!(score.team, Some(Team::Geelong));
         assert_eq!(score.complete, 78);
         assert_eq!(score.timestr, TimeStr::Other("Q4  4:36".to_string()));
         assert_eq!(
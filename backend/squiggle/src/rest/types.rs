@@ -21,6 +21,13 @@ pub struct Game {
     pub year: u16,
     pub date: String,
     pub tz: String,
+    /// Which final this is, 0 in the home and away season. Squiggle numbers them 2 for an
+    /// elimination final, 3 qualifying, 4 semi, 5 preliminary and 6 for the grand final.
+    #[serde(default)]
+    pub is_final: u8,
+    /// 1 for the grand final, 0 otherwise
+    #[serde(default)]
+    pub is_grand_final: u8,
 }
 
 #[cfg(test)]
@@ -53,5 +60,18 @@ pub mod test {
         assert_eq!(game.complete, 0);
         assert_eq!(game.away_score, 0);
         assert_eq!(game.home_score, 0);
+        assert_eq!(game.is_final, 0);
+        assert_eq!(game.is_grand_final, 0);
+    }
+
+    #[test]
+    fn test_finals_game() {
+        let game: Game = serde_json::from_str(r#"{"ateam":"Brisbane Lions","roundname":"Grand Final","hteamid":16,"round":27,"is_grand_final":1,"hteam":"Sydney","winnerteamid":2,"ateamid":2,"is_final":6,"venue":"M.C.G.","hscore":60,"winner":"Brisbane Lions","year":2024,"updated":"2024-09-28 17:31:02","ascore":120,"tz":"+10:00","complete":100,"localtime":"2024-09-28 14:30:00","timestr":"Full Time","hbehinds":6,"abehinds":12,"unixtime":1727497800,"agoals":18,"date":"2024-09-28 14:30:00","hgoals":9,"id":35950}"#).expect("Should deser");
+
+        assert_eq!(game.id, 35950);
+        assert_eq!(game.round, 27);
+        assert_eq!(game.is_final, 6);
+        assert_eq!(game.is_grand_final, 1);
+        assert_eq!(game.winner, Some(Team::Brisbane));
     }
 }
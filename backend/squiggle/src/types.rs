@@ -43,6 +43,58 @@ pub enum Team {
     WesternBulldogs = 18,
 }
 
+impl Team {
+    /// Lowercase name without spaces, suitable for URLs and topic names
+    #[must_use]
+    pub fn slug(&self) -> &'static str {
+        match self {
+            Team::Adelaide => "adelaide",
+            Team::Brisbane => "brisbane",
+            Team::Carlton => "carlton",
+            Team::Collingwood => "collingwood",
+            Team::Essendon => "essendon",
+            Team::Fremantle => "fremantle",
+            Team::Geelong => "geelong",
+            Team::GoldCoast => "goldcoast",
+            Team::GreaterWesternSydney => "gws",
+            Team::Hawthorn => "hawthorn",
+            Team::Melbourne => "melbourne",
+            Team::NorthMelbourne => "northmelbourne",
+            Team::PortAdelaide => "portadelaide",
+            Team::Richmond => "richmond",
+            Team::StKilda => "stkilda",
+            Team::Sydney => "sydney",
+            Team::WestCoast => "westcoast",
+            Team::WesternBulldogs => "westernbulldogs",
+        }
+    }
+
+    /// Short uppercase name, for where space is tight
+    #[must_use]
+    pub fn abbreviation(&self) -> &'static str {
+        match self {
+            Team::Adelaide => "ADE",
+            Team::Brisbane => "BRI",
+            Team::Carlton => "CARL",
+            Team::Collingwood => "COL",
+            Team::Essendon => "ESS",
+            Team::Fremantle => "FRE",
+            Team::Geelong => "GEE",
+            Team::GoldCoast => "GCS",
+            Team::GreaterWesternSydney => "GWS",
+            Team::Hawthorn => "HAW",
+            Team::Melbourne => "MEL",
+            Team::NorthMelbourne => "NTH",
+            Team::PortAdelaide => "PTA",
+            Team::Richmond => "RIC",
+            Team::StKilda => "STK",
+            Team::Sydney => "SYD",
+            Team::WestCoast => "WCE",
+            Team::WesternBulldogs => "WBD",
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone, strum_macros::Display)]
 pub enum TimeStr {
     #[serde(rename = "1/4 Time")]
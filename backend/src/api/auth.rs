@@ -0,0 +1,92 @@
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use sha2::{Digest, Sha256};
+
+use crate::store::types::Subscription;
+
+/// Header web push subscribers can send their subscription's `auth` key in, instead of the token
+pub const SUBSCRIPTION_AUTH_HEADER: &str = "x-subscription-auth";
+/// Header for the one-time code sent to a subscription that has nothing else to prove ownership
+/// with
+pub const CLAIM_CODE_HEADER: &str = "x-claim-code";
+
+/// A new secret for managing a subscription, only its hash is stored
+#[must_use]
+pub fn new_token() -> String {
+    URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+}
+
+#[must_use]
+pub fn hash_token(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Compares secrets in time that only depends on their length, so a caller can't find how much
+/// of a guess was right by timing it
+#[must_use]
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// What a caller has given to show they own a subscription, either the management token as a
+/// bearer token, or a web push subscription's `auth` key
+#[derive(Debug, Default)]
+pub struct Credentials {
+    token: Option<String>,
+    auth: Option<String>,
+    claim_code: Option<String>,
+}
+
+impl Credentials {
+    /// Whether these prove ownership of the subscription
+    #[must_use]
+    pub fn authorizes(&self, subscription: &Subscription) -> bool {
+        // only web push subscriptions have an auth key
+        let auth = self.auth.as_deref().is_some_and(|auth| {
+            !subscription.auth.is_empty() && constant_time_eq(auth, &subscription.auth)
+        });
+
+        self.has_token_for(subscription) || auth
+    }
+
+    /// Whether these include the subscription's current management token
+    #[must_use]
+    pub fn has_token_for(&self, subscription: &Subscription) -> bool {
+        self.token
+            .as_deref()
+            .zip(subscription.management_token_hash.as_deref())
+            .is_some_and(|(token, hash)| constant_time_eq(&hash_token(token), hash))
+    }
+
+    /// The one-time code sent to a subscription to claim it with, if one was given
+    #[must_use]
+    pub fn claim_code(&self) -> Option<&str> {
+        self.claim_code.as_deref()
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Credentials {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let header = |name| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+
+        Ok(Self {
+            token: header(axum::http::header::AUTHORIZATION.as_str())
+                .and_then(|value| value.strip_prefix("Bearer ").map(str::to_string)),
+            auth: header(SUBSCRIPTION_AUTH_HEADER),
+            claim_code: header(CLAIM_CODE_HEADER),
+        })
+    }
+}
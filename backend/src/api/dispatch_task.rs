@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+use sentry::Hub;
+use tokio::{task::JoinHandle, time::sleep};
+
+use crate::notifier::Notifier;
+
+/// How often the outbox is checked for deliveries that are due to be retried
+const DISPATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Periodically sends whatever is due in the outbox, which picks up retries and anything left
+/// unsent after a restart
+pub fn start_dispatch_task(notifier: Notifier) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            if let Err(err) = notifier.dispatch().await {
+                tracing::error!(?err, "Error dispatching deliveries");
+                Hub::current().capture_error(&err);
+            }
+
+            sleep(DISPATCH_INTERVAL).await;
+        }
+    })
+}
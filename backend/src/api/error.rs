@@ -16,12 +16,44 @@ pub enum ApiError {
     Notifier(#[from] crate::notifier::Error),
     #[error("Not authorized")]
     Unauthorized,
+    #[error("Invalid request: {0}")]
+    Invalid(String),
+    #[error("Subscription not found")]
+    NotFound,
+    #[error("Claim code needed")]
+    ClaimCodeNeeded,
+    #[error("Conflict: {0}")]
+    Conflict(String),
 }
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
-        if matches!(self, ApiError::Unauthorized) {
-            return StatusCode::UNAUTHORIZED.into_response();
+        match self {
+            ApiError::Unauthorized => return StatusCode::UNAUTHORIZED.into_response(),
+            ApiError::NotFound => return StatusCode::NOT_FOUND.into_response(),
+            ApiError::ClaimCodeNeeded => {
+                return (
+                    StatusCode::FORBIDDEN,
+                    format!(
+                        "A claim code has been sent to the subscription, subscribe again with it \
+                         in the {} header",
+                        super::auth::CLAIM_CODE_HEADER
+                    ),
+                )
+                    .into_response()
+            }
+            ApiError::Invalid(message) => {
+                return (StatusCode::UNPROCESSABLE_ENTITY, message).into_response()
+            }
+            ApiError::Conflict(message) => return (StatusCode::CONFLICT, message).into_response(),
+            // the subscriber's own server, not ours, so not worth reporting
+            ApiError::Notifier(crate::notifier::Error::Rejected(message)) => {
+                return (StatusCode::BAD_REQUEST, message).into_response()
+            }
+            ApiError::Notifier(crate::notifier::Error::DeliveryFailed(message)) => {
+                return (StatusCode::BAD_GATEWAY, message).into_response()
+            }
+            _ => {}
         }
         Hub::current().capture_error(&self);
         StatusCode::INTERNAL_SERVER_ERROR.into_response()
@@ -5,12 +5,21 @@ use sentry::Hub;
 use squiggle::{event, rest};
 use tokio::{task::JoinHandle, time::sleep};
 
-use crate::{notifier::Notifier, processor::Processor, store::Store};
+use crate::{mqtt::Publisher, notifier::Notifier, processor::Processor, store::Store};
 
-pub fn start_event_task(event_task_store: Store, event_task_notifier: Notifier) -> JoinHandle<()> {
+pub fn start_event_task(
+    event_task_store: Store,
+    event_task_notifier: Notifier,
+    event_task_publisher: Option<Publisher>,
+) -> JoinHandle<()> {
     tokio::spawn(async move {
         loop {
-            let res = event_task(event_task_store.clone(), event_task_notifier.clone()).await;
+            let res = event_task(
+                event_task_store.clone(),
+                event_task_notifier.clone(),
+                event_task_publisher.clone(),
+            )
+            .await;
             tracing::warn!("Event loop finished with {:?}", res);
 
             if let Err(err) = res {
@@ -27,12 +36,19 @@ pub fn start_event_task(event_task_store: Store, event_task_notifier: Notifier)
 #[error(transparent)]
 struct EventError(Box<dyn Error + Sync + Send>);
 
-async fn event_task(store: Store, notifier: Notifier) -> Result<(), EventError> {
+async fn event_task(
+    store: Store,
+    notifier: Notifier,
+    publisher: Option<Publisher>,
+) -> Result<(), EventError> {
     let rest_client = rest::Client::new("sam.vr.lewis@gmail.com - footyalerts")
         .map_err(|err| EventError(Box::new(err)))?;
     let mut event_client = event::client::Client::new("sam.vr.lewis@gmail.com - footyalerts")
         .map_err(|err| EventError(Box::new(err)))?;
-    let event_processor = Processor::new(store, rest_client, notifier);
+    let mut event_processor = Processor::new(store, rest_client, notifier);
+    if let Some(publisher) = publisher {
+        event_processor = event_processor.with_publisher(publisher);
+    }
     let stream = event_client.stream();
 
     pin_mut!(stream);
@@ -1,4 +1,8 @@
+/// Proving ownership of a subscription
+pub mod auth;
+pub mod dispatch_task;
 mod error;
 pub mod event_task;
 mod response;
 pub mod routes;
+pub mod season_task;
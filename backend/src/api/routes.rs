@@ -3,13 +3,18 @@ use std::{env, time::Duration};
 use axum::{
     extract::{Query, Request, State},
     http::StatusCode,
-    routing::{get, post},
+    routing::{delete, get, patch, post},
     Json, Router,
 };
 use axum_auth::AuthBearer;
+use chrono::{Datelike, NaiveTime, TimeDelta, Utc};
+use chrono_tz::Tz;
 use sentry::integrations::tower::{NewSentryLayer, SentryHttpLayer};
 use serde::{Deserialize, Serialize};
-use squiggle::{rest::types::Game, types::Team};
+use squiggle::{
+    rest::types::Game,
+    types::{GameId, Team},
+};
 use tower_http::{
     compression::CompressionLayer,
     cors::CorsLayer,
@@ -20,11 +25,28 @@ use tower_http::{
 };
 
 use crate::{
-    api::{error::ApiError, response::ApiResponse},
-    notifier::Notifier,
-    store::{Stats, Store},
+    api::{
+        auth::{self, Credentials},
+        error::ApiError,
+        response::ApiResponse,
+    },
+    channel::matrix,
+    notifier::{Notifier, PAYLOAD_VERSION},
+    store::{
+        types::{
+            Channel, DeliveryAttempt, GameFollow, Locale, Notification, Preferences, Scope, Snooze,
+            Style, QUIET_HOURS_FORMAT,
+        },
+        Stats, Store,
+    },
 };
 
+/// How many of a subscriber's most recent notifications are returned
+const NOTIFICATION_HISTORY_LIMIT: u32 = 50;
+
+/// Longest alerts can be held back for, a day covers watching a night game the next morning
+const MAX_SPOILER_DELAY: u32 = 24 * 60 * 60;
+
 #[derive(Clone)]
 struct SharedState {
     store: Store,
@@ -39,6 +61,18 @@ pub fn create_router(store: Store, notifier: Notifier) -> Router {
         .route("/games", get(games))
         .route("/subscription", get(get_subscription))
         .route("/subscription", post(create_subscription))
+        .route("/subscription", patch(update_subscription))
+        .route("/subscription", delete(delete_subscription))
+        .route(
+            "/subscription/notifications",
+            get(subscription_notifications),
+        )
+        .route("/subscription/change", post(change_subscription))
+        .route("/subscription/release", post(release_alerts))
+        .route("/subscription/snooze", post(snooze))
+        .route("/subscription/snooze", delete(unsnooze))
+        .route("/subscription/games", post(follow_game))
+        .route("/subscription/games", delete(unfollow_game))
         .route("/test_notification", post(test_notification))
         .route("/stats", get(stats))
         .with_state(state)
@@ -84,42 +118,298 @@ struct Params {
     endpoint: String,
 }
 
+/// Times of day as HH:MM, e.g. 22:00
+mod hours_minutes {
+    use chrono::NaiveTime;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::store::types::QUIET_HOURS_FORMAT as FORMAT;
+
+    pub fn serialize<S: Serializer>(time: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&time.format(FORMAT))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+        let time = String::deserialize(deserializer)?;
+        NaiveTime::parse_from_str(&time, FORMAT).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Local times that alerts shouldn't be sent between, the end can be the next day
+#[derive(Deserialize, Serialize)]
+struct QuietHours {
+    #[serde(with = "hours_minutes")]
+    start: NaiveTime,
+    #[serde(with = "hours_minutes")]
+    end: NaiveTime,
+}
+
 #[derive(Serialize)]
 struct SubscriptionOptions {
-    team: Option<Team>,
-    close_games: bool,
-    final_scores: bool,
-    quarter_scores: bool,
+    teams: Vec<Team>,
+    notifications: Preferences,
+    other_notifications: Preferences,
+    /// Single games followed
+    games: Vec<GameFollow>,
+    /// False once unsubscribed
+    active: bool,
+    channel: Channel,
+    timezone: Tz,
+    quiet_hours: Option<QuietHours>,
+    spoiler_delay: Option<u32>,
+    hold_alerts: bool,
+    locale: Locale,
+    style: Style,
+    scope: Scope,
+    snooze: Option<Snooze>,
 }
 
-impl From<crate::store::types::Subscription> for SubscriptionOptions {
-    fn from(value: crate::store::types::Subscription) -> Self {
+impl SubscriptionOptions {
+    fn new(value: crate::store::types::Subscription, games: Vec<GameFollow>) -> Self {
+        let timezone = value.timezone();
+        let quiet_hours = value
+            .quiet_hours()
+            .map(|(start, end)| QuietHours { start, end });
+        // snoozes until a time are only cleared when the next alert goes out
+        let snooze = value.snooze().filter(|snooze| match snooze {
+            Snooze::Until(until) => *until > Utc::now().timestamp(),
+            _ => true,
+        });
+
         Self {
-            team: value.team,
-            close_games: value.close_games,
-            final_scores: value.final_scores,
-            quarter_scores: value.quarter_scores,
+            teams: value.teams,
+            notifications: value.notifications,
+            other_notifications: value.other_notifications,
+            games,
+            active: value.active,
+            channel: value.channel,
+            timezone,
+            quiet_hours,
+            spoiler_delay: value.spoiler_delay,
+            hold_alerts: value.hold_alerts,
+            locale: value.locale,
+            style: value.style,
+            scope: value.scope,
+            snooze,
         }
     }
 }
 
-#[tracing::instrument(skip(state, params), err)]
+/// The subscription for `endpoint`, as long as the caller has shown they own it
+async fn authorize(
+    store: &Store,
+    endpoint: &str,
+    credentials: &Credentials,
+) -> Result<crate::store::types::Subscription, ApiError> {
+    let subscription = store
+        .get_subscription_for_endpoint(endpoint)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    if !credentials.authorizes(&subscription) {
+        return Err(ApiError::Unauthorized);
+    }
+
+    Ok(subscription)
+}
+
+#[tracing::instrument(skip(state, params, credentials), err)]
 async fn get_subscription(
     State(state): State<SharedState>,
+    credentials: Credentials,
     Query(params): Query<Params>,
-) -> Result<ApiResponse<Option<SubscriptionOptions>>, ApiError> {
+) -> Result<ApiResponse<SubscriptionOptions>, ApiError> {
     let endpoint =
         urlencoding::decode(&params.endpoint).map_err(ApiError::SubscriptionUrlDecoding)?;
-    let subscription = state.store.get_subscription_for_endpoint(&endpoint).await?;
 
     tracing::debug!("Trying to get subscription by endpoint {}", endpoint);
 
-    let response = match subscription {
-        None => ApiResponse::new(None, StatusCode::NOT_FOUND),
-        Some(subscription) => ApiResponse::new(Some(subscription.into()), StatusCode::OK),
+    let subscription = authorize(&state.store, &endpoint, &credentials).await?;
+    let games = state.store.get_game_follows(&endpoint).await?;
+
+    Ok(ApiResponse::new(
+        SubscriptionOptions::new(subscription, games),
+        StatusCode::OK,
+    ))
+}
+
+#[tracing::instrument(skip(state, params, credentials), err)]
+async fn subscription_notifications(
+    State(state): State<SharedState>,
+    credentials: Credentials,
+    Query(params): Query<Params>,
+) -> Result<ApiResponse<Vec<DeliveryAttempt>>, ApiError> {
+    let endpoint =
+        urlencoding::decode(&params.endpoint).map_err(ApiError::SubscriptionUrlDecoding)?;
+    authorize(&state.store, &endpoint, &credentials).await?;
+    let attempts = state
+        .store
+        .get_delivery_attempts(&endpoint, NOTIFICATION_HISTORY_LIMIT)
+        .await?;
+
+    Ok(ApiResponse::new(attempts, StatusCode::OK))
+}
+
+#[derive(Deserialize)]
+struct ReleaseParams {
+    endpoint: String,
+    /// Only release alerts for this game
+    game: Option<GameId>,
+}
+
+#[derive(Serialize)]
+struct Released {
+    released: u64,
+}
+
+/// "I'm caught up", sends the alerts held for the subscriber
+#[tracing::instrument(skip(state, params, credentials), err)]
+async fn release_alerts(
+    State(state): State<SharedState>,
+    credentials: Credentials,
+    Query(params): Query<ReleaseParams>,
+) -> Result<ApiResponse<Released>, ApiError> {
+    let endpoint =
+        urlencoding::decode(&params.endpoint).map_err(ApiError::SubscriptionUrlDecoding)?;
+    authorize(&state.store, &endpoint, &credentials).await?;
+    let released = state.notifier.release(&endpoint, params.game).await?;
+
+    Ok(ApiResponse::new(Released { released }, StatusCode::OK))
+}
+
+/// How long to snooze for, from the subscriber
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SnoozeFor {
+    /// Unix timestamp
+    Until(i64),
+    Weeks(u8),
+    NextRound,
+    Finals,
+}
+
+#[derive(Serialize)]
+struct Snoozed {
+    snooze: Snooze,
+}
+
+/// Mutes alerts for a while, e.g. a bye or a bad run of form
+#[tracing::instrument(skip(state, params, credentials), err)]
+async fn snooze(
+    State(state): State<SharedState>,
+    credentials: Credentials,
+    Query(params): Query<Params>,
+    Json(snooze_for): Json<SnoozeFor>,
+) -> Result<ApiResponse<Snoozed>, ApiError> {
+    let endpoint =
+        urlencoding::decode(&params.endpoint).map_err(ApiError::SubscriptionUrlDecoding)?;
+    authorize(&state.store, &endpoint, &credentials).await?;
+
+    let now = Utc::now();
+    let this_round = state.store.get_this_round_games().await?;
+    let snooze = match snooze_for {
+        SnoozeFor::Until(until) if until <= now.timestamp() => {
+            return Err(ApiError::Invalid(
+                "Can't snooze until a time that's passed".to_string(),
+            ))
+        }
+        SnoozeFor::Until(until) => Snooze::Until(until),
+        SnoozeFor::Weeks(0) => {
+            return Err(ApiError::Invalid(
+                "Can't snooze for no time at all".to_string(),
+            ))
+        }
+        SnoozeFor::Weeks(weeks) => {
+            Snooze::Until((now + TimeDelta::weeks(weeks.into())).timestamp())
+        }
+        SnoozeFor::NextRound => {
+            let game = this_round.first().ok_or_else(|| {
+                ApiError::Invalid("There's no round to snooze until yet".to_string())
+            })?;
+            Snooze::Round {
+                year: game.year,
+                round: game.round + 1,
+            }
+        }
+        SnoozeFor::Finals => Snooze::Finals {
+            year: this_round.first().map_or_else(
+                || now.year().try_into().unwrap_or_default(),
+                |game| game.year,
+            ),
+        },
+    };
+
+    state
+        .store
+        .snooze_subscription(&endpoint, Some(snooze))
+        .await?;
+
+    Ok(ApiResponse::new(Snoozed { snooze }, StatusCode::OK))
+}
+
+#[tracing::instrument(skip(state, params, credentials), err)]
+async fn unsnooze(
+    State(state): State<SharedState>,
+    credentials: Credentials,
+    Query(params): Query<Params>,
+) -> Result<ApiResponse<()>, ApiError> {
+    let endpoint =
+        urlencoding::decode(&params.endpoint).map_err(ApiError::SubscriptionUrlDecoding)?;
+    authorize(&state.store, &endpoint, &credentials).await?;
+    state.store.snooze_subscription(&endpoint, None).await?;
+
+    Ok(ApiResponse::new((), StatusCode::OK))
+}
+
+#[derive(Deserialize)]
+struct FollowGame {
+    endpoint: String,
+    #[serde(flatten)]
+    follow: GameFollow,
+}
+
+/// Follows one game, e.g. a friend's team in a Showdown, until full time
+#[tracing::instrument(skip(state, body, credentials), err)]
+async fn follow_game(
+    State(state): State<SharedState>,
+    credentials: Credentials,
+    Json(body): Json<FollowGame>,
+) -> Result<ApiResponse<()>, ApiError> {
+    authorize(&state.store, &body.endpoint, &credentials).await?;
+    let status = if state
+        .store
+        .follow_game(&body.endpoint, &body.follow)
+        .await?
+    {
+        StatusCode::CREATED
+    } else {
+        StatusCode::NOT_FOUND
     };
 
-    Ok(response)
+    Ok(ApiResponse::new((), status))
+}
+
+#[derive(Deserialize)]
+struct UnfollowParams {
+    endpoint: String,
+    game: GameId,
+}
+
+#[tracing::instrument(skip(state, params, credentials), err)]
+async fn unfollow_game(
+    State(state): State<SharedState>,
+    credentials: Credentials,
+    Query(params): Query<UnfollowParams>,
+) -> Result<ApiResponse<()>, ApiError> {
+    let endpoint =
+        urlencoding::decode(&params.endpoint).map_err(ApiError::SubscriptionUrlDecoding)?;
+    authorize(&state.store, &endpoint, &credentials).await?;
+    let status = if state.store.unfollow_game(&endpoint, params.game).await? {
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    };
+
+    Ok(ApiResponse::new((), status))
 }
 
 #[derive(Deserialize)]
@@ -134,46 +424,562 @@ struct WebPush {
     pub keys: Keys,
 }
 
+#[derive(Deserialize)]
+struct Ntfy {
+    pub topic_url: String,
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Gotify {
+    pub server_url: String,
+    pub app_token: String,
+}
+
+#[derive(Deserialize)]
+struct Matrix {
+    pub homeserver_url: String,
+    pub room_id: String,
+    pub access_token: String,
+}
+
+/// Exactly one of these keys is expected in the subscription body
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Destination {
+    WebPush(WebPush),
+    Ntfy(Ntfy),
+    Gotify(Gotify),
+    Matrix(Matrix),
+}
+
 #[derive(Deserialize)]
 struct Subscription {
+    /// Teams to get alerts for, every game if empty
+    #[serde(default)]
+    pub teams: Vec<Team>,
+    /// Single team, from clients from before `teams`
     pub team: Option<Team>,
+    /// Kinds of alert wanted for games with one of `teams`, or every game if it's empty
+    pub notifications: Option<Preferences>,
+    /// Kinds of alert wanted for games without any of `teams`
+    #[serde(default)]
+    pub other_notifications: Preferences,
+    /// Used instead of `notifications` by clients from before it
+    #[serde(default)]
     pub close_games: bool,
+    #[serde(default)]
     pub final_scores: bool,
+    #[serde(default)]
     pub quarter_scores: bool,
-    pub web_push: WebPush,
+    /// Web push payload format the client understands, plain text if not given
+    #[serde(default)]
+    pub payload_version: u8,
+    pub timezone: Option<Tz>,
+    pub quiet_hours: Option<QuietHours>,
+    /// Seconds to hold alerts for after the real event
+    pub spoiler_delay: Option<u32>,
+    /// Hold alerts until they're released
+    #[serde(default)]
+    pub hold_alerts: bool,
+    #[serde(default)]
+    pub locale: Locale,
+    #[serde(default)]
+    pub style: Style,
+    /// Games wanted besides the ones followed on their own, every game if not given
+    #[serde(default)]
+    pub scope: Scope,
+    #[serde(flatten)]
+    pub destination: Destination,
+}
+
+impl Subscription {
+    fn validate(&self) -> Result<(), String> {
+        validate_options(
+            Some(&self.teams),
+            self.quiet_hours.as_ref(),
+            self.spoiler_delay,
+            Some(self.payload_version),
+        )
+    }
 }
 
 impl From<Subscription> for crate::store::types::Subscription {
     fn from(value: Subscription) -> Self {
+        let (channel, endpoint, p256dh, auth, token) = match value.destination {
+            Destination::WebPush(web_push) => (
+                Channel::WebPush,
+                web_push.endpoint,
+                web_push.keys.p256dh,
+                web_push.keys.auth,
+                None,
+            ),
+            Destination::Ntfy(ntfy) => (
+                Channel::Ntfy,
+                ntfy.topic_url,
+                String::new(),
+                String::new(),
+                ntfy.token,
+            ),
+            Destination::Gotify(gotify) => (
+                Channel::Gotify,
+                format!("{}/message", gotify.server_url.trim_end_matches('/')),
+                String::new(),
+                String::new(),
+                Some(gotify.app_token),
+            ),
+            Destination::Matrix(room) => (
+                Channel::Matrix,
+                matrix::room_endpoint(&room.homeserver_url, &room.room_id),
+                String::new(),
+                String::new(),
+                Some(room.access_token),
+            ),
+        };
+
+        let mut teams = value.teams;
+        teams.extend(value.team);
+        let notifications = value.notifications.unwrap_or_else(|| {
+            let mut notifications = Preferences::default();
+            if value.quarter_scores {
+                notifications = notifications
+                    .with(Notification::EndOfFirstQuarter)
+                    .with(Notification::EndOfSecondQuarter)
+                    .with(Notification::EndOfThirdQuarter);
+            }
+            if value.final_scores {
+                notifications = notifications.with(Notification::EndOfGame);
+            }
+            if value.close_games {
+                notifications = notifications.with(Notification::CloseGame);
+            }
+            notifications
+        });
+
         Self {
-            team: value.team,
-            close_games: value.close_games,
-            final_scores: value.final_scores,
-            quarter_scores: value.quarter_scores,
-            endpoint: value.web_push.endpoint,
-            p256dh: value.web_push.keys.p256dh,
-            auth: value.web_push.keys.auth,
+            teams,
+            notifications,
+            other_notifications: value.other_notifications,
+            endpoint,
+            p256dh,
+            auth,
+            channel,
+            token,
+            payload_version: value.payload_version,
+            timezone: value.timezone.map(|timezone| timezone.name().to_string()),
+            quiet_hours_start: value
+                .quiet_hours
+                .as_ref()
+                .map(|quiet_hours| quiet_hours.start.format(QUIET_HOURS_FORMAT).to_string()),
+            quiet_hours_end: value
+                .quiet_hours
+                .map(|quiet_hours| quiet_hours.end.format(QUIET_HOURS_FORMAT).to_string()),
+            spoiler_delay: value.spoiler_delay,
+            hold_alerts: value.hold_alerts,
+            locale: value.locale,
+            style: value.style,
+            scope: value.scope,
+            active: true,
+            management_token_hash: None,
+            superseded_by: None,
+            snoozed_until: None,
+            snoozed_year: None,
+            snoozed_round: None,
         }
     }
 }
 
-#[tracing::instrument(skip(state, subscription), err)]
+#[derive(Serialize)]
+struct Created {
+    /// Secret needed to read or change the subscription, only given out here. Left out when the
+    /// caller already has the current one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    management_token: Option<String>,
+}
+
+/// How long a claim code can be used for once it's sent
+const CLAIM_CODE_TTL_SECS: i64 = 15 * 60;
+
+/// Subscribes, or replaces an existing subscription's preferences. Replacing needs the
+/// subscription's management token, or the same web push keys or channel token it was created
+/// with. Subscriptions from before tokens without any of those are sent a one-time claim code
+/// instead, to be given back when subscribing again.
+#[tracing::instrument(skip(state, subscription, credentials), err)]
 async fn create_subscription(
     State(state): State<SharedState>,
+    credentials: Credentials,
     Json(subscription): Json<Subscription>,
+) -> Result<ApiResponse<Created>, ApiError> {
+    subscription.validate().map_err(ApiError::Invalid)?;
+    let mut subscription: crate::store::types::Subscription = subscription.into();
+
+    if let Some(existing) = state
+        .store
+        .get_subscription_for_endpoint(&subscription.endpoint)
+        .await?
+    {
+        // the same browser subscribing again
+        let same_keys = !existing.auth.is_empty()
+            && auth::constant_time_eq(&existing.auth, &subscription.auth)
+            && existing.p256dh == subscription.p256dh;
+        // the same ntfy topic, Gotify app or Matrix account, with its secret
+        let same_token = existing.channel == subscription.channel
+            && existing
+                .token
+                .as_deref()
+                .zip(subscription.token.as_deref())
+                .is_some_and(|(existing, token)| auth::constant_time_eq(existing, token));
+        if !(same_keys || same_token || credentials.authorizes(&existing)) {
+            // from before tokens, and without keys to prove ownership with
+            let unclaimed = existing.management_token_hash.is_none() && existing.auth.is_empty();
+            if !unclaimed {
+                return Err(ApiError::Unauthorized);
+            }
+            claim(&state, &existing, &credentials).await?;
+        }
+        // saving preferences again doesn't end a snooze
+        subscription.set_snooze(existing.snooze());
+        // nor does it log out whoever else has the token
+        if credentials.has_token_for(&existing) {
+            subscription.management_token_hash = existing.management_token_hash;
+        }
+    }
+
+    let management_token = if subscription.management_token_hash.is_none() {
+        let token = auth::new_token();
+        subscription.management_token_hash = Some(auth::hash_token(&token));
+        Some(token)
+    } else {
+        None
+    };
+    state.store.add_subscription(subscription).await?;
+
+    Ok(ApiResponse::new(
+        Created { management_token },
+        StatusCode::CREATED,
+    ))
+}
+
+/// Checks the claim code given for a subscription that has nothing else to prove ownership with,
+/// or sends it one if none was given and there isn't one out already
+async fn claim(
+    state: &SharedState,
+    subscription: &crate::store::types::Subscription,
+    credentials: &Credentials,
+) -> Result<(), ApiError> {
+    let endpoint = &subscription.endpoint;
+    let now = chrono::Utc::now().timestamp();
+
+    if let Some(code) = credentials.claim_code() {
+        return if state
+            .store
+            .redeem_claim(endpoint, &auth::hash_token(code), now)
+            .await?
+        {
+            Ok(())
+        } else {
+            Err(ApiError::Unauthorized)
+        };
+    }
+
+    let code = auth::new_token();
+    let expires_at = now + CLAIM_CODE_TTL_SECS;
+    if state
+        .store
+        .start_claim(endpoint, &auth::hash_token(&code), now, expires_at)
+        .await?
+    {
+        if let Err(error) = state.notifier.send_claim_code(subscription, &code).await {
+            state.store.cancel_claim(endpoint).await?;
+            return Err(error.into());
+        }
+    }
+
+    Err(ApiError::ClaimCodeNeeded)
+}
+
+/// Fields of a subscription that can be changed on their own, anything not given is left as is.
+/// `null` clears the optional ones.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SubscriptionPatch {
+    teams: Option<Vec<Team>>,
+    notifications: Option<Preferences>,
+    other_notifications: Option<Preferences>,
+    timezone: Option<Tz>,
+    #[serde(default, deserialize_with = "present")]
+    quiet_hours: Option<Option<QuietHours>>,
+    #[serde(default, deserialize_with = "present")]
+    spoiler_delay: Option<Option<u32>>,
+    hold_alerts: Option<bool>,
+    locale: Option<Locale>,
+    style: Option<Style>,
+    scope: Option<Scope>,
+    payload_version: Option<u8>,
+}
+
+/// Tells a field that's `null` apart from one that's missing, missing fields are `None`
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Checks preferences given when subscribing or changing a subscription, anything not given is
+/// left alone so is fine
+fn validate_options(
+    teams: Option<&[Team]>,
+    quiet_hours: Option<&QuietHours>,
+    spoiler_delay: Option<u32>,
+    payload_version: Option<u8>,
+) -> Result<(), String> {
+    if let Some(teams) = teams {
+        if teams
+            .iter()
+            .enumerate()
+            .any(|(i, team)| teams[..i].contains(team))
+        {
+            return Err(String::from("teams can't have duplicates"));
+        }
+    }
+    if let Some(quiet_hours) = quiet_hours {
+        if quiet_hours.start == quiet_hours.end {
+            return Err(String::from(
+                "quiet_hours can't start and end at the same time",
+            ));
+        }
+    }
+    if let Some(spoiler_delay) = spoiler_delay {
+        if spoiler_delay > MAX_SPOILER_DELAY {
+            return Err(format!(
+                "spoiler_delay can't be more than {MAX_SPOILER_DELAY} seconds"
+            ));
+        }
+    }
+    if let Some(payload_version) = payload_version {
+        if payload_version > PAYLOAD_VERSION {
+            return Err(format!(
+                "payload_version can't be more than {PAYLOAD_VERSION}"
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+impl SubscriptionPatch {
+    fn validate(&self) -> Result<(), String> {
+        validate_options(
+            self.teams.as_deref(),
+            self.quiet_hours.as_ref().and_then(Option::as_ref),
+            self.spoiler_delay.flatten(),
+            self.payload_version,
+        )
+    }
+
+    fn apply(self, subscription: &mut crate::store::types::Subscription) {
+        if let Some(teams) = self.teams {
+            subscription.teams = teams;
+        }
+        if let Some(notifications) = self.notifications {
+            subscription.notifications = notifications;
+        }
+        if let Some(other_notifications) = self.other_notifications {
+            subscription.other_notifications = other_notifications;
+        }
+        if let Some(timezone) = self.timezone {
+            subscription.timezone = Some(timezone.name().to_string());
+        }
+        if let Some(quiet_hours) = self.quiet_hours {
+            let format = |time: NaiveTime| time.format(QUIET_HOURS_FORMAT).to_string();
+            subscription.quiet_hours_start = quiet_hours.as_ref().map(|hours| format(hours.start));
+            subscription.quiet_hours_end = quiet_hours.map(|hours| format(hours.end));
+        }
+        if let Some(spoiler_delay) = self.spoiler_delay {
+            subscription.spoiler_delay = spoiler_delay;
+        }
+        if let Some(hold_alerts) = self.hold_alerts {
+            subscription.hold_alerts = hold_alerts;
+        }
+        if let Some(locale) = self.locale {
+            subscription.locale = locale;
+        }
+        if let Some(style) = self.style {
+            subscription.style = style;
+        }
+        if let Some(scope) = self.scope {
+            subscription.scope = scope;
+        }
+        if let Some(payload_version) = self.payload_version {
+            subscription.payload_version = payload_version;
+        }
+    }
+}
+
+/// Sent by the service worker when the browser moves its push subscription
+#[derive(Deserialize)]
+struct SubscriptionChange {
+    old: WebPush,
+    new: WebPush,
+}
+
+/// Moves a web push subscription to its new endpoint, keeping its preferences and history
+#[tracing::instrument(skip(state, credentials, change), err)]
+async fn change_subscription(
+    State(state): State<SharedState>,
+    credentials: Credentials,
+    Json(change): Json<SubscriptionChange>,
+) -> Result<ApiResponse<SubscriptionOptions>, ApiError> {
+    if change.old.endpoint == change.new.endpoint {
+        return Err(ApiError::Invalid(
+            "The new endpoint is the same as the old one".to_string(),
+        ));
+    }
+
+    let existing = state
+        .store
+        .get_subscription_for_endpoint(&change.old.endpoint)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    // the service worker has no token, just the old subscription's keys
+    let same_keys =
+        !existing.auth.is_empty() && auth::constant_time_eq(&existing.auth, &change.old.keys.auth);
+    if !(same_keys || credentials.authorizes(&existing)) {
+        return Err(ApiError::Unauthorized);
+    }
+    if existing.channel != Channel::WebPush {
+        return Err(ApiError::Invalid(
+            "Only web push subscriptions can change endpoint".to_string(),
+        ));
+    }
+
+    match existing.superseded_by {
+        // the browser telling us again
+        Some(superseded_by) if superseded_by == change.new.endpoint => {}
+        Some(_) => {
+            return Err(ApiError::Invalid(
+                "The subscription has already moved to another endpoint".to_string(),
+            ))
+        }
+        None => {
+            // moving onto someone else's subscription would take it over
+            if let Some(replaced) = state
+                .store
+                .get_subscription_for_endpoint(&change.new.endpoint)
+                .await?
+            {
+                let same_keys = !replaced.auth.is_empty()
+                    && auth::constant_time_eq(&replaced.auth, &change.new.keys.auth);
+                if replaced.superseded_by.is_none()
+                    && !(same_keys || credentials.authorizes(&replaced))
+                {
+                    return Err(ApiError::Conflict(
+                        "There's already a subscription for the new endpoint".to_string(),
+                    ));
+                }
+            }
+            // removed or moved by another request since it was read
+            if !state
+                .store
+                .supersede_subscription(
+                    &change.old.endpoint,
+                    &change.new.endpoint,
+                    &change.new.keys.p256dh,
+                    &change.new.keys.auth,
+                )
+                .await?
+            {
+                return Err(ApiError::Conflict(
+                    "The subscription was changed by another request, try again".to_string(),
+                ));
+            }
+        }
+    }
+
+    let subscription = state
+        .store
+        .get_subscription_for_endpoint(&change.new.endpoint)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    let games = state.store.get_game_follows(&change.new.endpoint).await?;
+
+    Ok(ApiResponse::new(
+        SubscriptionOptions::new(subscription, games),
+        StatusCode::OK,
+    ))
+}
+
+/// Changes some of a subscription's preferences, without the client having to send them all
+#[tracing::instrument(skip(state, params, credentials, patch), err)]
+async fn update_subscription(
+    State(state): State<SharedState>,
+    credentials: Credentials,
+    Query(params): Query<Params>,
+    Json(patch): Json<SubscriptionPatch>,
+) -> Result<ApiResponse<SubscriptionOptions>, ApiError> {
+    patch.validate().map_err(ApiError::Invalid)?;
+
+    let endpoint =
+        urlencoding::decode(&params.endpoint).map_err(ApiError::SubscriptionUrlDecoding)?;
+    let mut subscription = authorize(&state.store, &endpoint, &credentials).await?;
+
+    patch.apply(&mut subscription);
+    state.store.add_subscription(subscription).await?;
+
+    // read back so the response is what was stored
+    let subscription = authorize(&state.store, &endpoint, &credentials).await?;
+    let games = state.store.get_game_follows(&endpoint).await?;
+
+    Ok(ApiResponse::new(
+        SubscriptionOptions::new(subscription, games),
+        StatusCode::OK,
+    ))
+}
+
+#[derive(Deserialize)]
+struct DeleteParams {
+    endpoint: String,
+    /// Remove everything stored about the subscription, rather than just stopping alerts
+    #[serde(default)]
+    purge: bool,
+}
+
+/// Unsubscribes
+#[tracing::instrument(skip(state, params, credentials), err)]
+async fn delete_subscription(
+    State(state): State<SharedState>,
+    credentials: Credentials,
+    Query(params): Query<DeleteParams>,
 ) -> Result<ApiResponse<()>, ApiError> {
-    state.store.add_subscription(subscription.into()).await?;
+    let endpoint =
+        urlencoding::decode(&params.endpoint).map_err(ApiError::SubscriptionUrlDecoding)?;
+    authorize(&state.store, &endpoint, &credentials).await?;
+    let found = if params.purge {
+        state.store.purge_subscription(&endpoint).await?
+    } else {
+        state.store.delete_subscription(&endpoint).await?
+    };
+
+    let status = if found {
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    };
 
-    Ok(ApiResponse::new((), StatusCode::CREATED))
+    Ok(ApiResponse::new((), status))
 }
 
-#[tracing::instrument(skip(state, params), err)]
+#[tracing::instrument(skip(state, params, credentials), err)]
 async fn test_notification(
     State(state): State<SharedState>,
+    credentials: Credentials,
     Query(params): Query<Params>,
 ) -> Result<ApiResponse<()>, ApiError> {
     let endpoint =
         urlencoding::decode(&params.endpoint).map_err(ApiError::SubscriptionUrlDecoding)?;
+    authorize(&state.store, &endpoint, &credentials).await?;
     state.notifier.send_test_notification(&endpoint).await?;
 
     tracing::debug!("Sending test notification for {}", endpoint);
@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use sentry::Hub;
+use squiggle::rest;
+use tokio::{task::JoinHandle, time::sleep};
+
+use crate::{notifier::Notifier, season::Season, store::Store};
+
+/// How often to check whether a season push is due
+const SEASON_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Periodically checks the fixture for the end of the season or the start of the next one
+pub fn start_season_task(store: Store, notifier: Notifier) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let rest_client = match rest::Client::new("sam.vr.lewis@gmail.com - footyalerts") {
+            Ok(rest_client) => rest_client,
+            Err(err) => {
+                tracing::error!(?err, "Couldn't create squiggle client for season task");
+                Hub::current().capture_error(&err);
+                return;
+            }
+        };
+        let season = Season::new(store, rest_client, notifier);
+
+        loop {
+            if let Err(err) = season.tick(Utc::now()).await {
+                tracing::error!(?err, "Error checking the season");
+                Hub::current().capture_error(&err);
+            }
+
+            sleep(SEASON_INTERVAL).await;
+        }
+    })
+}
@@ -0,0 +1,86 @@
+use reqwest::StatusCode;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    channel::{check_status, Alert, Error},
+    store::types::{Notification, Subscription},
+};
+
+/// Posts to a Gotify application, see <https://gotify.net/docs/pushmsg>
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+}
+
+#[derive(Serialize)]
+struct Message<'a> {
+    title: &'a str,
+    message: &'a str,
+    priority: u8,
+    extras: serde_json::Value,
+}
+
+/// Gotify priorities run from 0 to 10, the Android app only makes a sound from 4 and
+/// pops up a heads-up notification from 8
+fn priority(kind: Option<Notification>) -> u8 {
+    let Some(kind) = kind else { return 5 };
+
+    match kind {
+        Notification::EndOfFirstQuarter
+        | Notification::EndOfSecondQuarter
+        | Notification::EndOfThirdQuarter => 5,
+        Notification::EndOfGame => 7,
+        Notification::CloseGame => 8,
+    }
+}
+
+fn tag(kind: Option<Notification>) -> &'static str {
+    let Some(kind) = kind else { return "test" };
+
+    match kind {
+        Notification::EndOfFirstQuarter
+        | Notification::EndOfSecondQuarter
+        | Notification::EndOfThirdQuarter => "quarter",
+        Notification::EndOfGame => "full_time",
+        Notification::CloseGame => "close_game",
+    }
+}
+
+impl Client {
+    pub fn new(http: reqwest::Client) -> Self {
+        Self { http }
+    }
+
+    #[tracing::instrument(skip(self, subscription), fields(endpoint = subscription.endpoint), err)]
+    pub async fn send(
+        &self,
+        subscription: &Subscription,
+        alert: &Alert,
+    ) -> Result<StatusCode, Error> {
+        let token = subscription
+            .token
+            .as_ref()
+            .ok_or(Error::MissingCredential("Gotify app token"))?;
+
+        let message = Message {
+            title: &alert.title,
+            message: &alert.body,
+            priority: priority(alert.kind),
+            extras: json!({
+                "client::notification": { "click": { "url": alert.url } },
+                "footyalerts::alert": { "kind": tag(alert.kind), "game_id": alert.game_id },
+            }),
+        };
+
+        let response = self
+            .http
+            .post(&subscription.endpoint)
+            .header("X-Gotify-Key", token)
+            .json(&message)
+            .send()
+            .await?;
+
+        check_status(&response)
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    channel::{check_status, Alert, Error},
+    store::{types::Subscription, Store},
+};
+
+/// Makes transaction ids unique within a process, the timestamp covers restarts
+static TRANSACTION_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Posts to a Matrix room through the client-server API, see
+/// <https://spec.matrix.org/v1.11/client-server-api/#put_matrixclientv3roomsroomidsendeventtypetxnid>.
+///
+/// The first alert for a game is posted as a new message, later alerts for the same game edit
+/// that message so the room has a single live score per game.
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    store: Store,
+}
+
+#[derive(Deserialize)]
+struct SendResponse {
+    event_id: String,
+}
+
+/// The subscription endpoint for a room, messages are sent relative to this
+#[must_use]
+pub fn room_endpoint(homeserver_url: &str, room_id: &str) -> String {
+    format!(
+        "{}/_matrix/client/v3/rooms/{}",
+        homeserver_url.trim_end_matches('/'),
+        urlencoding::encode(room_id)
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn transaction_id() -> String {
+    let count = TRANSACTION_COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!(
+        "footyalerts.{}.{count}",
+        chrono::Utc::now().timestamp_millis()
+    )
+}
+
+impl Client {
+    pub fn new(http: reqwest::Client, store: Store) -> Self {
+        Self { http, store }
+    }
+
+    #[tracing::instrument(skip(self, subscription), fields(endpoint = subscription.endpoint), err)]
+    pub async fn send(
+        &self,
+        subscription: &Subscription,
+        alert: &Alert,
+    ) -> Result<StatusCode, Error> {
+        let token = subscription
+            .token
+            .as_ref()
+            .ok_or(Error::MissingCredential("Matrix access token"))?;
+
+        let content = json!({
+            "msgtype": "m.text",
+            "body": format!("{}\n{}\n{}", alert.title, alert.body, alert.url),
+            "format": "org.matrix.custom.html",
+            "formatted_body": format!(
+                "<b>{}</b><br>{} (<a href=\"{}\">live scores</a>)",
+                escape_html(&alert.title),
+                escape_html(&alert.body),
+                escape_html(&alert.url),
+            ),
+        });
+
+        let original = match alert.game_id {
+            Some(game_id) => {
+                self.store
+                    .get_matrix_message(&subscription.endpoint, game_id)
+                    .await?
+            }
+            None => None,
+        };
+
+        let message = match &original {
+            Some(event_id) => json!({
+                "msgtype": "m.text",
+                "body": format!("* {}", content["body"].as_str().unwrap_or_default()),
+                "format": "org.matrix.custom.html",
+                "formatted_body": format!(
+                    "* {}",
+                    content["formatted_body"].as_str().unwrap_or_default()
+                ),
+                "m.new_content": content,
+                "m.relates_to": { "rel_type": "m.replace", "event_id": event_id },
+            }),
+            None => content,
+        };
+
+        let response = self
+            .http
+            .put(format!(
+                "{}/send/m.room.message/{}",
+                subscription.endpoint,
+                transaction_id()
+            ))
+            .bearer_auth(token)
+            .json(&message)
+            .send()
+            .await?;
+
+        let status = check_status(&response)?;
+
+        // remember the message so later alerts for the game edit it rather than posting again
+        if let (None, Some(game_id)) = (original, alert.game_id) {
+            let response: SendResponse = response.json().await?;
+            self.store
+                .record_matrix_message(&subscription.endpoint, game_id, &response.event_id)
+                .await?;
+        }
+
+        Ok(status)
+    }
+}
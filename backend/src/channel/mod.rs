@@ -0,0 +1,119 @@
+/// Gotify application messages
+pub mod gotify;
+/// Matrix room messages
+pub mod matrix;
+/// ntfy topic publishing
+pub mod ntfy;
+/// Browser web push
+pub mod web_push;
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::{header::RETRY_AFTER, StatusCode};
+use serde::Serialize;
+use squiggle::types::{GameId, Team};
+
+use crate::store::types::Notification;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Request: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("Server responded with {0}")]
+    Status(StatusCode),
+    /// Rate limited, or the server is struggling
+    #[error("Server unavailable, responded with {0}")]
+    Unavailable(StatusCode, Option<Duration>),
+    #[error("Subscription has no {0}")]
+    MissingCredential(&'static str),
+    #[error("Store: {0}")]
+    Store(#[from] crate::store::Error),
+}
+
+impl Error {
+    /// Whether retrying can never succeed, meaning the subscription should be deactivated
+    #[must_use]
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            Error::Status(status) => matches!(
+                *status,
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::NOT_FOUND
+            ),
+            Error::MissingCredential(_) => true,
+            Error::Request(_) | Error::Unavailable(..) | Error::Store(_) => false,
+        }
+    }
+
+    /// Status the receiving server responded with, if it got that far
+    #[must_use]
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::Status(status) | Error::Unavailable(status, _) => Some(*status),
+            Error::Request(err) => err.status(),
+            Error::MissingCredential(_) | Error::Store(_) => None,
+        }
+    }
+
+    /// How long the server asked us to wait before trying again
+    #[must_use]
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::Unavailable(_, retry_after) => *retry_after,
+            Error::Request(_)
+            | Error::Status(_)
+            | Error::MissingCredential(_)
+            | Error::Store(_) => None,
+        }
+    }
+
+    /// Whether the server is rate limiting us, rather than failing just this request
+    #[must_use]
+    pub fn is_rate_limited(&self) -> bool {
+        matches!(self, Error::Unavailable(StatusCode::TOO_MANY_REQUESTS, _))
+    }
+}
+
+/// A rendered alert, ready to be handed to a delivery channel
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub title: String,
+    pub body: String,
+    /// `None` for test notifications and season pushes
+    pub kind: Option<Notification>,
+    pub game_id: Option<GameId>,
+    pub scores: Option<Scores>,
+    /// Link that opens the game on the site
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Scores {
+    pub home_team: Team,
+    pub away_team: Team,
+    pub home_score: u16,
+    pub away_score: u16,
+}
+
+fn check_status(response: &reqwest::Response) -> Result<StatusCode, Error> {
+    let status = response.status();
+    if status.is_success() {
+        Ok(status)
+    } else if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+        Err(Error::Unavailable(status, retry_after(response)))
+    } else {
+        Err(Error::Status(status))
+    }
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
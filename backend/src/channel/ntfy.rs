@@ -0,0 +1,66 @@
+use reqwest::StatusCode;
+
+use crate::{
+    channel::{check_status, Alert, Error},
+    store::types::{Notification, Subscription},
+};
+
+/// Publishes to an ntfy topic, see <https://docs.ntfy.sh/publish/>
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+}
+
+/// ntfy priorities run from 1 (min) to 5 (max)
+fn priority(kind: Option<Notification>) -> u8 {
+    let Some(kind) = kind else { return 3 };
+
+    match kind {
+        Notification::EndOfFirstQuarter
+        | Notification::EndOfSecondQuarter
+        | Notification::EndOfThirdQuarter => 3,
+        Notification::EndOfGame => 4,
+        Notification::CloseGame => 5,
+    }
+}
+
+/// Tags that match an emoji shortcode are shown as an emoji by ntfy clients
+fn tags(kind: Option<Notification>) -> &'static str {
+    let Some(kind) = kind else { return "football" };
+
+    match kind {
+        Notification::EndOfFirstQuarter
+        | Notification::EndOfSecondQuarter
+        | Notification::EndOfThirdQuarter => "football,stopwatch",
+        Notification::EndOfGame => "football,checkered_flag",
+        Notification::CloseGame => "football,rotating_light",
+    }
+}
+
+impl Client {
+    pub fn new(http: reqwest::Client) -> Self {
+        Self { http }
+    }
+
+    #[tracing::instrument(skip(self, subscription), fields(endpoint = subscription.endpoint), err)]
+    pub async fn send(
+        &self,
+        subscription: &Subscription,
+        alert: &Alert,
+    ) -> Result<StatusCode, Error> {
+        let mut request = self
+            .http
+            .post(&subscription.endpoint)
+            .header("Title", &alert.title)
+            .header("Priority", priority(alert.kind).to_string())
+            .header("Tags", tags(alert.kind))
+            .header("Click", &alert.url)
+            .body(alert.body.clone());
+
+        if let Some(token) = &subscription.token {
+            request = request.bearer_auth(token);
+        }
+
+        check_status(&request.send().await?)
+    }
+}
@@ -0,0 +1,96 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use chrono::Utc;
+use reqwest::{header::CONTENT_LENGTH, StatusCode, Url};
+use web_push::{
+    request_builder, PartialVapidSignatureBuilder, SubscriptionInfo, VapidSignature, WebPushError,
+    WebPushMessage,
+};
+
+use crate::channel::{check_status, Error};
+
+/// How long a VAPID signature is reused for. They're valid for twelve hours, this leaves plenty
+/// of margin for messages that sit in a push service before being delivered.
+const SIGNATURE_REUSE_SECS: i64 = 60 * 60;
+
+/// Sends encrypted messages to browser push services, see <https://www.rfc-editor.org/rfc/rfc8030>
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    sig_builder: PartialVapidSignatureBuilder,
+    /// Signatures only depend on the push service's origin, so one per origin is shared by all
+    /// its subscribers
+    signatures: Arc<Mutex<HashMap<String, (VapidSignature, i64)>>>,
+}
+
+impl Client {
+    pub fn new(http: reqwest::Client, sig_builder: PartialVapidSignatureBuilder) -> Self {
+        Self {
+            http,
+            sig_builder,
+            signatures: Arc::default(),
+        }
+    }
+
+    /// VAPID signature for the subscription's push service
+    pub fn sign(&self, subscription: &SubscriptionInfo) -> Result<VapidSignature, WebPushError> {
+        let Some(audience) = audience(&subscription.endpoint) else {
+            return self.sig_builder.clone().add_sub_info(subscription).build();
+        };
+
+        let now = Utc::now().timestamp();
+        let mut signatures = self.signatures.lock().expect("signature lock poisoned");
+        if let Some((signature, signed_at)) = signatures.get(&audience) {
+            if now - signed_at < SIGNATURE_REUSE_SECS {
+                return Ok(signature.clone());
+            }
+        }
+
+        let signature = self
+            .sig_builder
+            .clone()
+            .add_sub_info(subscription)
+            .build()?;
+        signatures.insert(audience, (signature.clone(), now));
+
+        Ok(signature)
+    }
+
+    #[tracing::instrument(skip(self, message), fields(endpoint = %message.endpoint), err)]
+    pub async fn send(&self, message: WebPushMessage) -> Result<StatusCode, Error> {
+        let request = request_builder::build_request::<Vec<u8>>(message);
+
+        let mut builder = self.http.post(request.uri().to_string());
+        for (name, value) in request.headers() {
+            // reqwest sets this from the body
+            if name.as_str() != CONTENT_LENGTH.as_str() {
+                builder = builder.header(name.as_str(), value.as_bytes());
+            }
+        }
+
+        check_status(&builder.body(request.into_body()).send().await?)
+    }
+}
+
+/// The `aud` claim web-push signs with, the endpoint's scheme and host
+fn audience(endpoint: &str) -> Option<String> {
+    let url = Url::parse(endpoint).ok()?;
+
+    Some(format!("{}://{}", url.scheme(), url.host_str()?))
+}
+
+/// Push services respond with 404 or 410 once the browser has dropped the subscription
+#[must_use]
+pub fn is_expired(err: &Error) -> bool {
+    matches!(err, Error::Status(StatusCode::NOT_FOUND | StatusCode::GONE))
+}
+
+/// Any other client error, e.g. a bad VAPID signature or too big a payload, will be refused
+/// again if it's retried
+#[must_use]
+pub fn is_rejected(err: &Error) -> bool {
+    matches!(err, Error::Status(status) if status.is_client_error())
+}
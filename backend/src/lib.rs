@@ -1,4 +1,15 @@
 pub mod api;
+/// Delivery channels for alerts
+pub mod channel;
+/// Optional MQTT publishing of game events and alerts
+pub mod mqtt;
 pub mod notifier;
+/// Per notification kind delivery policies
+pub mod policy;
 pub mod processor;
+pub mod season;
 pub mod store;
+/// Localised alert text
+pub mod templates;
+/// Per push service backoff
+pub mod throttle;
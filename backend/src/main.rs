@@ -1,9 +1,15 @@
 use std::{env, error::Error};
 
 use footy_alerts::{
-    api::{event_task::start_event_task, routes::create_router},
-    notifier::Notifier,
+    api::{
+        dispatch_task::start_dispatch_task, event_task::start_event_task, routes::create_router,
+        season_task::start_season_task,
+    },
+    mqtt::Publisher,
+    notifier::{FanOut, Notifier},
+    policy::Policies,
     store::Store,
+    templates::Catalog,
 };
 use sentry::ClientInitGuard;
 use tracing_subscriber::{fmt, layer::SubscriberExt};
@@ -51,15 +57,44 @@ fn main() -> Result<(), Box<dyn Error>> {
 
 async fn async_main() -> Result<(), Box<dyn Error>> {
     let store = Store::new(&env::var("DATABASE_URL").expect("Database URL not found")).await?;
+    let policies = match env::var("NOTIFICATION_POLICIES") {
+        Ok(policies) => serde_json::from_str(&policies)?,
+        Err(_) => Policies::default(),
+    };
+    let fan_out = match env::var("NOTIFICATION_FAN_OUT") {
+        Ok(fan_out) => serde_json::from_str(&fan_out)?,
+        Err(_) => FanOut::default(),
+    };
+    // checked here so a bad template stops startup rather than going out in an alert
+    let templates = match env::var("NOTIFICATION_TEMPLATES") {
+        Ok(templates) => Catalog::default().with_config(serde_json::from_str(&templates)?)?,
+        Err(_) => Catalog::default(),
+    };
     let notifier = Notifier::new(
         store.clone(),
         &env::var("NOTIFICATION_PRIVATE_KEY").expect("Priv key not found"),
-    )?;
+    )?
+    .with_policies(policies)
+    .with_fan_out(fan_out)
+    .with_templates(templates);
+
+    let publisher = match env::var("MQTT_URL") {
+        Ok(url) => {
+            let credentials = env::var("MQTT_USERNAME")
+                .ok()
+                .zip(env::var("MQTT_PASSWORD").ok());
+            Some(Publisher::new(&url, credentials)?)
+        }
+        Err(_) => None,
+    };
+    tracing::info!(mqtt = publisher.is_some(), "Running with MQTT publishing");
 
     let event_task_store = store.clone();
     let event_task_notifier = notifier.clone();
 
-    let _handle = start_event_task(event_task_store, event_task_notifier);
+    let _handle = start_event_task(event_task_store, event_task_notifier, publisher);
+    let _dispatch_handle = start_dispatch_task(notifier.clone());
+    let _season_handle = start_season_task(store.clone(), notifier.clone());
 
     let router = create_router(store, notifier);
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use rumqttc::{AsyncClient, ClientError, EventLoop, MqttOptions, OptionError, QoS};
+use serde::Serialize;
+use squiggle::{event::types::Event, rest::types::Game, types::Team};
+
+use crate::channel::Alert;
+
+/// Root of every topic we publish to
+const TOPIC_PREFIX: &str = "footyalerts";
+
+/// How long to wait before reconnecting after the broker connection drops
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, thiserror::Error)]
+pub enum InitError {
+    #[error("Invalid MQTT url: {0}")]
+    Url(#[from] OptionError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Client: {0}")]
+    Client(#[from] ClientError),
+    #[error("Serialize: {0}")]
+    Serialize(#[from] serde_json::Error),
+}
+
+/// Publishes game events and alerts to an MQTT broker, e.g. for home automation.
+///
+/// Topics published to:
+/// - `footyalerts/game/{id}/score`: the latest state of the game, retained
+/// - `footyalerts/game/{id}/event`: every event received for the game
+/// - `footyalerts/game/{id}/alert`: every alert generated for the game
+/// - `footyalerts/team/{team}/score`: goals and behinds kicked by the team
+/// - `footyalerts/team/{team}/alert`: every alert generated for the team's games
+#[derive(Clone)]
+pub struct Publisher {
+    client: AsyncClient,
+}
+
+impl Publisher {
+    /// `url` is of the form `mqtt://host:1883` or `mqtts://host:8883`, a `client_id` query
+    /// parameter can be given to override the default client id.
+    pub fn new(url: &str, credentials: Option<(String, String)>) -> Result<Self, InitError> {
+        let url = if url.contains("client_id=") {
+            url.to_string()
+        } else if url.contains('?') {
+            format!("{url}&client_id=footyalerts")
+        } else {
+            format!("{url}?client_id=footyalerts")
+        };
+
+        let mut options = MqttOptions::parse_url(url)?;
+        if let Some((username, password)) = credentials {
+            options.set_credentials(username, password);
+        }
+
+        let (client, event_loop) = AsyncClient::new(options, 100);
+        tokio::spawn(poll_event_loop(event_loop));
+
+        Ok(Self { client })
+    }
+
+    #[tracing::instrument(skip(self, game, event), err)]
+    pub fn publish_event(&self, game: &Game, event: &Event) -> Result<(), Error> {
+        self.publish(&format!("game/{}/score", game.id), true, game)?;
+        self.publish(&format!("game/{}/event", game.id), false, event)?;
+
+        if let Event::Score(score) = event {
+            if let Some(team) = &score.team {
+                self.publish(&team_topic(team, "score"), false, score)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self, game, alert), err)]
+    pub fn publish_alert(&self, game: &Game, alert: &Alert) -> Result<(), Error> {
+        self.publish(&format!("game/{}/alert", game.id), false, alert)?;
+        self.publish(&team_topic(&game.home_team, "alert"), false, alert)?;
+        self.publish(&team_topic(&game.away_team, "alert"), false, alert)?;
+
+        Ok(())
+    }
+
+    /// Queues the message without waiting, so a missing broker never holds up event processing
+    fn publish(&self, topic: &str, retain: bool, payload: &impl Serialize) -> Result<(), Error> {
+        let payload = serde_json::to_vec(payload)?;
+        self.client.try_publish(
+            format!("{TOPIC_PREFIX}/{topic}"),
+            QoS::AtLeastOnce,
+            retain,
+            payload,
+        )?;
+
+        Ok(())
+    }
+}
+
+fn team_topic(team: &Team, suffix: &str) -> String {
+    format!("team/{}/{suffix}", team.slug())
+}
+
+/// The event loop has to be polled for messages to actually be sent to the broker
+async fn poll_event_loop(mut event_loop: EventLoop) {
+    loop {
+        if let Err(err) = event_loop.poll().await {
+            tracing::warn!(error = ?err, "MQTT connection error");
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+}
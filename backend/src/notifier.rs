@@ -1,17 +1,131 @@
-use std::{fmt, fmt::Formatter};
+use std::{
+    collections::HashMap,
+    fmt,
+    fmt::Formatter,
+    time::{Duration, Instant},
+};
 
-use futures::{StreamExt, TryFutureExt};
+use chrono::{DateTime, FixedOffset, NaiveDateTime, Utc};
+use futures::StreamExt;
+use rand::Rng;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
 use squiggle::{
     rest::types::Game,
-    types::{Team, TimeStr},
+    types::{GameId, Team, TimeStr},
 };
 use web_push::{
-    ContentEncoding, IsahcWebPushClient, PartialVapidSignatureBuilder, SubscriptionInfo,
-    SubscriptionKeys, Urgency, VapidSignatureBuilder, WebPushClient, WebPushError,
+    ContentEncoding, SubscriptionInfo, SubscriptionKeys, VapidSignatureBuilder, WebPushError,
     WebPushMessageBuilder, URL_SAFE_NO_PAD,
 };
 
-use crate::store::{types::Subscription, Store};
+use crate::{
+    channel::{self, gotify, matrix, ntfy, web_push as push, Alert, Scores},
+    policy::{Collapse, Policies, Policy},
+    store::{
+        types::{
+            Alert as AlertRecord, Channel, Delivery, DeliveryAttempt, DeliveryOutcome,
+            DeliveryStatus, Final, Locale, SeasonEvent, Style, Subscription,
+        },
+        Store,
+    },
+    templates::{Catalog, Templates, Values},
+    throttle::{self, Throttle},
+};
+
+/// How long a claimed delivery is left alone before it's assumed the sender died
+const DISPATCH_LEASE_SECS: i64 = 5 * 60;
+
+/// Delay before the first retry of a failed delivery, doubled for each further attempt
+const RETRY_BASE_SECS: f64 = 10.0;
+
+/// Longest delay between retries
+const RETRY_MAX_SECS: f64 = 10.0 * 60.0;
+
+/// Range a push service's `Retry-After` is kept to, so a zero doesn't retry in a tight loop and
+/// a huge one doesn't overflow
+const RETRY_AFTER_SECS: std::ops::RangeInclusive<i64> = 1..=24 * 60 * 60;
+
+/// Seconds alerts are held for at most, so they don't pile up for a subscriber who never
+/// releases them
+const MAX_HELD_SECS: i64 = 24 * 60 * 60;
+
+/// Seconds a broadcast is worth delivering for, they're not about anything happening right now
+const BROADCAST_TTL_SECS: u32 = 24 * 60 * 60;
+
+/// Where alerts link to when they're clicked
+pub const SITE_URL: &str = "https://footyalerts.fyi";
+
+/// Version of the JSON web push payload, bump this for breaking changes to [`Payload`]
+pub const PAYLOAD_VERSION: u8 = 1;
+
+/// When the game's first bounce is, `date` is given in the game's `tz`
+#[must_use]
+pub fn game_start(game: &Game) -> Option<DateTime<Utc>> {
+    let date = NaiveDateTime::parse_from_str(&game.date, "%Y-%m-%d %H:%M:%S").ok()?;
+    let tz: FixedOffset = game.tz.parse().ok()?;
+    let start = date.and_local_timezone(tz).single()?;
+    Some(start.with_timezone(&Utc))
+}
+
+/// Test notifications are only useful while the user is looking at the site
+const TEST_POLICY: Policy = Policy {
+    collapse: Collapse::Keep,
+    urgency: crate::policy::Urgency::High,
+    ttl_seconds: 60,
+};
+
+/// Icon used when an alert isn't about a particular team
+const DEFAULT_ICON: &str = "/apple-touch-icon.png";
+
+/// JSON web push payload, read by the service worker
+#[derive(Debug, Serialize)]
+pub struct Payload<'a> {
+    pub version: u8,
+    pub title: &'a str,
+    pub body: &'a str,
+    /// Notifications with the same tag replace each other on the device
+    pub tag: String,
+    pub icon: String,
+    pub url: &'a str,
+    pub game_id: Option<GameId>,
+    pub kind: Option<crate::store::types::Notification>,
+    pub scores: Option<&'a Scores>,
+}
+
+impl<'a> Payload<'a> {
+    /// Shows the icon of the subscriber's team if they're playing, otherwise the home team's
+    #[must_use]
+    pub fn new(alert: &'a Alert, teams: &[Team], collapse: Collapse) -> Self {
+        let icon = match &alert.scores {
+            Some(scores) => {
+                let team = teams
+                    .iter()
+                    .find(|team| **team == scores.home_team || **team == scores.away_team)
+                    .unwrap_or(&scores.home_team);
+                format!("/team_icons/{}.png", team.slug())
+            }
+            None => String::from(DEFAULT_ICON),
+        };
+
+        let tag = match (alert.game_id, alert.kind) {
+            (Some(game_id), Some(kind)) => collapse.key(game_id, kind),
+            _ => String::from("footyalerts"),
+        };
+
+        Self {
+            version: PAYLOAD_VERSION,
+            title: &alert.title,
+            body: &alert.body,
+            tag,
+            icon,
+            url: &alert.url,
+            game_id: alert.game_id,
+            kind: alert.kind,
+            scores: alert.scores.as_ref(),
+        }
+    }
+}
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -19,29 +133,139 @@ pub enum Error {
     WebPush(#[from] WebPushError),
     #[error("Store: {0}")]
     Store(#[from] crate::store::Error),
+    /// The subscriber's endpoint turned the alert down and always will, e.g. it's expired
+    #[error("Rejected: {0}")]
+    Rejected(String),
+    /// The subscriber's endpoint couldn't be reached or failed, it might work later
+    #[error("Delivery failed: {0}")]
+    DeliveryFailed(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeliveryError {
+    #[error("Web push: {0}")]
+    WebPush(#[from] WebPushError),
+    #[error("Channel: {0}")]
+    Channel(#[from] channel::Error),
+    #[error("Stored alert: {0}")]
+    Alert(#[from] serde_json::Error),
+}
+
+impl DeliveryError {
+    fn status(&self) -> Option<StatusCode> {
+        match self {
+            DeliveryError::Channel(err) => err.status(),
+            DeliveryError::WebPush(_) | DeliveryError::Alert(_) => None,
+        }
+    }
+
+    /// Seconds until the next attempt, as asked for by the server or with exponential backoff
+    fn retry_delay(&self, attempts: u32) -> i64 {
+        match self {
+            DeliveryError::Channel(err) => err.retry_after().map_or_else(
+                || retry_delay(attempts),
+                |retry_after| {
+                    i64::try_from(retry_after.as_secs())
+                        .unwrap_or(i64::MAX)
+                        .clamp(*RETRY_AFTER_SECS.start(), *RETRY_AFTER_SECS.end())
+                },
+            ),
+            DeliveryError::WebPush(_) | DeliveryError::Alert(_) => retry_delay(attempts),
+        }
+    }
+
+    /// Whether the whole push service should be backed off from, not just this subscription
+    fn is_throttling(&self) -> bool {
+        match self {
+            DeliveryError::Channel(err) => err.is_rate_limited() || err.retry_after().is_some(),
+            DeliveryError::WebPush(_) | DeliveryError::Alert(_) => false,
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
 enum PushError {
     #[error("Push failed for endpoint: {1} with error {0}")]
-    Expired(WebPushError, String),
+    Expired(DeliveryError, String),
     #[error("Transient error {0}")]
-    Other(WebPushError),
+    Other(DeliveryError),
+    #[error("Undeliverable {0}")]
+    Undeliverable(DeliveryError),
+    /// Not attempted, because the push service asked us to back off or it's the subscriber's
+    /// quiet hours
+    #[error("Deferred until {0}")]
+    Deferred(i64),
+}
+
+impl From<PushError> for Error {
+    fn from(value: PushError) -> Self {
+        match value {
+            PushError::Expired(..) | PushError::Undeliverable(_) => {
+                Error::Rejected(value.to_string())
+            }
+            PushError::Other(_) | PushError::Deferred(_) => {
+                Error::DeliveryFailed(value.to_string())
+            }
+        }
+    }
+}
+
+impl PushError {
+    fn status(&self) -> Option<StatusCode> {
+        match self {
+            PushError::Expired(err, _) | PushError::Other(err) | PushError::Undeliverable(err) => {
+                err.status()
+            }
+            PushError::Deferred(_) => None,
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum InitError {
     #[error("sig builder: {0}")]
     SigBuilder(WebPushError),
-    #[error("client: {0}")]
-    Client(WebPushError),
+    #[error("http client: {0}")]
+    HttpClient(reqwest::Error),
 }
 
 #[derive(Clone)]
 pub struct Notifier {
     store: Store,
-    sig_builder: PartialVapidSignatureBuilder,
-    client: IsahcWebPushClient,
+    web_push: push::Client,
+    ntfy: ntfy::Client,
+    gotify: gotify::Client,
+    matrix: matrix::Client,
+    policies: Policies,
+    fan_out: FanOut,
+    throttle: Throttle,
+    templates: Catalog,
+}
+
+/// How deliveries are sent out of the outbox
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct FanOut {
+    /// How many deliveries are read from the outbox at a time
+    pub batch_size: u32,
+    /// Most deliveries in flight overall
+    pub concurrency: usize,
+    /// Most deliveries in flight to any one push service
+    pub per_domain: usize,
+    /// Seconds an alert waits for others to the same subscriber, so they go out together as one
+    /// digest. 0 sends alerts straight away.
+    pub digest_window: u32,
+}
+
+impl Default for FanOut {
+    fn default() -> Self {
+        Self {
+            batch_size: 500,
+            concurrency: 64,
+            per_domain: 16,
+            digest_window: 0,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -86,37 +310,150 @@ pub enum Notification {
 }
 
 impl Notification {
-    fn to_notification_text(&self) -> String {
-        match self {
-            Notification::EndOfQuarter {
-                quarter,
-                home_team,
-                away_team,
-                home_score,
-                away_score,
-            } => {
-                format!("End of {quarter}: {home_team} {home_score} - {away_team} {away_score}")
+    fn scores(&self) -> Scores {
+        let (Notification::EndOfQuarter {
+            home_team,
+            away_team,
+            home_score,
+            away_score,
+            ..
+        }
+        | Notification::EndOfGame {
+            home_team,
+            away_team,
+            home_score,
+            away_score,
+        }
+        | Notification::CloseGame {
+            home_team,
+            away_team,
+            home_score,
+            away_score,
+            ..
+        }) = self;
+
+        Scores {
+            home_team: home_team.clone(),
+            away_team: away_team.clone(),
+            home_score: *home_score,
+            away_score: *away_score,
+        }
+    }
+
+    /// What the placeholders in the notification's templates are filled in with
+    fn values(&self) -> Values {
+        Values {
+            scores: Some(self.scores()),
+            quarter: match self {
+                Notification::EndOfQuarter { quarter, .. } => Some(quarter.to_string()),
+                Notification::EndOfGame { .. } | Notification::CloseGame { .. } => None,
+            },
+            clock: match self {
+                Notification::CloseGame { time_str, .. } => Some(time_str.to_string()),
+                Notification::EndOfQuarter { .. } | Notification::EndOfGame { .. } => None,
+            },
+            ..Values::default()
+        }
+    }
+
+    /// The alert for the notification, titled with the final's name if the game is one
+    #[must_use]
+    pub fn to_alert(
+        &self,
+        game_id: GameId,
+        final_round: Option<Final>,
+        templates: &Templates,
+    ) -> Alert {
+        let message = templates.get(self.into());
+        let values = self.values();
+        let title = message.title.render(&values);
+        Alert {
+            title: match final_round {
+                Some(final_round) => format!("{}: {title}", templates.finals.name(final_round)),
+                None => title,
+            },
+            body: message.body.render(&values),
+            kind: Some(self.into()),
+            game_id: Some(game_id),
+            scores: Some(self.scores()),
+            url: format!("{SITE_URL}/?game={game_id}"),
+        }
+    }
+
+    /// e.g. "3/4 Time"
+    fn label(&self, templates: &Templates) -> String {
+        templates.get(self.into()).label.render(&self.values())
+    }
+
+    /// e.g. "GEE 64-52 HAW"
+    fn summary(&self, templates: &Templates) -> String {
+        templates.summary.render(&self.values())
+    }
+}
+
+impl Notification {
+    /// What gets stored so the notification can be rebuilt when it's delivered
+    #[must_use]
+    pub fn to_record(&self, game_id: GameId) -> AlertRecord {
+        let scores = self.scores();
+        let timestr = match self {
+            Notification::CloseGame { time_str, .. } => serde_json::to_string(time_str).ok(),
+            Notification::EndOfQuarter { .. } | Notification::EndOfGame { .. } => None,
+        };
+
+        AlertRecord {
+            game_id,
+            notification: self.into(),
+            home_team: scores.home_team,
+            away_team: scores.away_team,
+            home_score: scores.home_score,
+            away_score: scores.away_score,
+            timestr,
+        }
+    }
+}
+
+impl TryFrom<&AlertRecord> for Notification {
+    type Error = serde_json::Error;
+
+    fn try_from(value: &AlertRecord) -> Result<Self, Self::Error> {
+        let home_team = value.home_team.clone();
+        let away_team = value.away_team.clone();
+        let home_score = value.home_score;
+        let away_score = value.away_score;
+        let end_of_quarter = |quarter| Notification::EndOfQuarter {
+            quarter,
+            home_team: home_team.clone(),
+            away_team: away_team.clone(),
+            home_score,
+            away_score,
+        };
+
+        let notification = match value.notification {
+            crate::store::types::Notification::EndOfFirstQuarter => end_of_quarter(Quarter::First),
+            crate::store::types::Notification::EndOfSecondQuarter => {
+                end_of_quarter(Quarter::Second)
             }
-            Notification::EndOfGame {
+            crate::store::types::Notification::EndOfThirdQuarter => end_of_quarter(Quarter::Third),
+            crate::store::types::Notification::EndOfGame => Notification::EndOfGame {
                 home_team,
                 away_team,
                 home_score,
                 away_score,
-            } => {
-                format!("End of game: {home_team} {home_score} - {away_team} {away_score}")
-            }
-            Notification::CloseGame {
+            },
+            crate::store::types::Notification::CloseGame => Notification::CloseGame {
+                time_str: match &value.timestr {
+                    Some(timestr) => serde_json::from_str(timestr)?,
+                    None => TimeStr::Other(String::new()),
+                },
                 home_team,
                 away_team,
                 home_score,
                 away_score,
-                time_str,
-            } => {
-                format!(
-                    "Close game ({time_str}): {home_team} {home_score} - {away_team} {away_score}"
-                )
-            }
-        }
+            },
+        };
+
+        Ok(notification)
     }
 }
 
@@ -138,90 +475,411 @@ impl Notifier {
     pub fn new(store: Store, private_key: &str) -> Result<Self, InitError> {
         let sig_builder = VapidSignatureBuilder::from_base64_no_sub(private_key, URL_SAFE_NO_PAD)
             .map_err(InitError::SigBuilder)?;
-        let client = IsahcWebPushClient::new().map_err(InitError::Client)?;
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(InitError::HttpClient)?;
         Ok(Self {
+            matrix: matrix::Client::new(http.clone(), store.clone()),
             store,
-            sig_builder,
-            client,
+            web_push: push::Client::new(http.clone(), sig_builder),
+            ntfy: ntfy::Client::new(http.clone()),
+            gotify: gotify::Client::new(http),
+            policies: Policies::default(),
+            fan_out: FanOut::default(),
+            throttle: Throttle::new(FanOut::default().per_domain),
+            templates: Catalog::default(),
         })
     }
 
+    #[must_use]
+    pub fn with_policies(self, policies: Policies) -> Self {
+        Self { policies, ..self }
+    }
+
+    #[must_use]
+    pub fn with_templates(self, templates: Catalog) -> Self {
+        Self { templates, ..self }
+    }
+
+    /// Alert text for a locale and style
+    #[must_use]
+    pub fn templates(&self, locale: Locale, style: Style) -> &Templates {
+        self.templates.get(locale, style)
+    }
+
+    #[must_use]
+    pub fn with_fan_out(self, fan_out: FanOut) -> Self {
+        Self {
+            fan_out,
+            throttle: Throttle::new(fan_out.per_domain),
+            ..self
+        }
+    }
+
+    /// Records the alert and adds a pending delivery of it to the outbox for everyone subscribed
+    /// to it, they're sent by [`Notifier::dispatch`]. Returns `None` if the alert was already
+    /// recorded.
     #[tracing::instrument(skip(self), err)]
-    pub async fn notify(&self, game: Game, notification: Notification) -> Result<(), Error> {
-        let db_notification = crate::store::types::Notification::from(&notification);
-        let users_to_notify = self
+    pub async fn record(&self, alert: &AlertRecord) -> Result<Option<u64>, Error> {
+        let policy = self.policies.get(alert.notification);
+        let now = Utc::now();
+        let expires_at = now.timestamp() + i64::from(policy.ttl_seconds);
+
+        let send_at = now.timestamp() + i64::from(self.fan_out.digest_window);
+
+        Ok(self
             .store
-            .get_subscriptions_for_notification(game.home_team, game.away_team, db_notification)
+            .record_alert(alert, now.timestamp(), send_at, expires_at)
+            .await?)
+    }
+
+    /// Sends the subscriber's held alerts, for one game or all of them, other than any held for
+    /// too long
+    #[tracing::instrument(skip(self), err)]
+    pub async fn release(&self, endpoint: &str, game: Option<GameId>) -> Result<u64, Error> {
+        let now = Utc::now().timestamp();
+        self.store
+            .expire_deliveries(now, now - MAX_HELD_SECS)
             .await?;
+        let released = self
+            .store
+            .release_held_deliveries(endpoint, game, now)
+            .await?;
+
+        if released > 0 {
+            self.dispatch().await?;
+        }
+
+        Ok(released)
+    }
+
+    /// Sends every delivery in the outbox that's due. Transient failures are retried with
+    /// exponential backoff until the alert expires.
+    #[tracing::instrument(skip(self), err)]
+    pub async fn dispatch(&self) -> Result<(), Error> {
+        let now = Utc::now().timestamp();
+        let expired = self
+            .store
+            .expire_deliveries(now, now - MAX_HELD_SECS)
+            .await?;
+        if expired > 0 {
+            tracing::warn!(expired, "Alerts expired before they could be delivered");
+        }
 
-        let notification = notification.to_notification_text();
+        loop {
+            let now = Utc::now().timestamp();
+            let deliveries = self
+                .store
+                .claim_deliveries(
+                    now,
+                    now + i64::from(self.fan_out.digest_window),
+                    now + DISPATCH_LEASE_SECS,
+                    self.fan_out.batch_size,
+                )
+                .await?;
 
-        let futures = users_to_notify
-            .into_iter()
-            .map(|user| async { self.send_user_notification(&notification, user).await })
-            .collect::<Vec<_>>();
+            if deliveries.is_empty() {
+                return Ok(());
+            }
 
-        let stream = futures::stream::iter(futures).buffer_unordered(10);
-        let results = stream.collect::<Vec<Result<(), PushError>>>().await;
+            let results = futures::stream::iter(digests(deliveries))
+                .map(|deliveries| async move {
+                    let _permit = self
+                        .throttle
+                        .acquire(&deliveries[0].subscription.endpoint)
+                        .await;
+                    let (attempt, result) = self.send_deliveries(&deliveries).await;
+                    (deliveries, attempt, result)
+                })
+                .buffer_unordered(self.fan_out.concurrency)
+                .collect::<Vec<_>>()
+                .await;
 
-        for res in results {
-            let Err(err) = res else { continue };
+            // written together, as one transaction per page is far cheaper for SQLite than one
+            // per delivery
+            let now = Utc::now().timestamp();
+            let mut outcomes = Vec::with_capacity(results.len());
+            let mut attempts = Vec::with_capacity(results.len());
+            for (deliveries, attempt, result) in results {
+                for delivery in &deliveries {
+                    outcomes.push((delivery.id, self.outcome(delivery, &result, now).await?));
+                }
+                attempts.extend(attempt);
+            }
 
-            match err {
-                PushError::Expired(err, endpoint) => {
-                    tracing::info!(error=?err, endpoint, "Error indicating endpoint expired");
-                    if let Err(err) = self.store.delete_subscription(&endpoint).await {
-                        tracing::error!(?err, endpoint, "Couldn't delete expired subscription");
-                    }
+            self.store
+                .record_outcomes(&outcomes, &attempts, now)
+                .await?;
+        }
+    }
+
+    /// Sends deliveries that are all to the same subscriber, as a digest if there's more than one
+    async fn send_deliveries(
+        &self,
+        deliveries: &[Delivery],
+    ) -> (Option<DeliveryAttempt>, Result<(), PushError>) {
+        let first = &deliveries[0];
+        let endpoint = &first.subscription.endpoint;
+        let now = Utc::now().timestamp();
+        if let Some(until) = self.throttle.blocked_until(endpoint, now) {
+            return (None, Err(PushError::Deferred(until)));
+        }
+
+        // held until the morning, alerts that won't last that long are dropped when they expire
+        if let Some(until) = first.subscription.quiet_until(Utc::now()) {
+            return (None, Err(PushError::Deferred(until.timestamp())));
+        }
+
+        let expires_at = deliveries
+            .iter()
+            .map(|delivery| delivery.expires_at)
+            .min()
+            .unwrap_or(now);
+        let ttl = u32::try_from(expires_at - now).unwrap_or(0);
+
+        let notifications = match deliveries
+            .iter()
+            .map(|delivery| Notification::try_from(&delivery.alert))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(notifications) => notifications,
+            Err(err) => return (None, Err(PushError::Undeliverable(err.into()))),
+        };
+        let templates = self
+            .templates
+            .get(first.subscription.locale, first.subscription.style);
+        let alert = match notifications.as_slice() {
+            [notification] => {
+                notification.to_alert(first.alert.game_id, first.final_round(), templates)
+            }
+            notifications => digest(notifications, templates),
+        };
+
+        let (attempt, result) = self.attempt(&alert, &first.subscription, ttl).await;
+
+        if let Err(PushError::Other(err)) = &result {
+            let attempts = deliveries
+                .iter()
+                .map(|delivery| delivery.attempts)
+                .max()
+                .unwrap_or_default();
+            self.back_off(endpoint, err, attempts);
+        }
+
+        (Some(attempt), result)
+    }
+
+    /// Backs off from the whole push service straight away if it's throttling us, so the rest of
+    /// the batch waits too. Returns when it can be sent to again.
+    fn back_off(&self, endpoint: &str, err: &DeliveryError, attempts: u32) -> Option<i64> {
+        if !err.is_throttling() {
+            return None;
+        }
+
+        let until = Utc::now()
+            .timestamp()
+            .saturating_add(err.retry_delay(attempts));
+        tracing::warn!(
+            domain = throttle::domain(endpoint),
+            until,
+            "Push service throttling"
+        );
+        self.throttle.block(endpoint, until);
+
+        Some(until)
+    }
+
+    /// Decides what to do with a delivery now it's been attempted
+    async fn outcome(
+        &self,
+        delivery: &Delivery,
+        result: &Result<(), PushError>,
+        now: i64,
+    ) -> Result<DeliveryOutcome, Error> {
+        let err = match result {
+            Ok(()) => {
+                return Ok(DeliveryOutcome::Completed {
+                    status: DeliveryStatus::Delivered,
+                    error: None,
+                })
+            }
+            Err(err) => err,
+        };
+
+        let error = err.to_string();
+        let expired = DeliveryOutcome::Completed {
+            status: DeliveryStatus::Expired,
+            error: Some(error.clone()),
+        };
+
+        let outcome = match err {
+            PushError::Expired(err, endpoint) => {
+                tracing::info!(error=?err, endpoint, "Error indicating endpoint expired");
+                self.store.delete_subscription(endpoint).await?;
+                DeliveryOutcome::Completed {
+                    status: DeliveryStatus::Failed,
+                    error: Some(error),
                 }
-                PushError::Other(err) => {
-                    tracing::warn!(error=?err, "Transient web push error");
+            }
+            PushError::Undeliverable(err) => {
+                tracing::error!(error=?err, "Undeliverable alert");
+                DeliveryOutcome::Completed {
+                    status: DeliveryStatus::Failed,
+                    error: Some(error),
                 }
             }
+            PushError::Deferred(until) if *until >= delivery.expires_at => expired,
+            PushError::Deferred(until) => DeliveryOutcome::Deferred {
+                next_attempt_at: *until,
+            },
+            PushError::Other(err) => {
+                let blocked_until = self
+                    .throttle
+                    .blocked_until(&delivery.subscription.endpoint, now)
+                    .unwrap_or_default();
+                let next_attempt_at = now
+                    .saturating_add(err.retry_delay(delivery.attempts))
+                    .max(blocked_until);
+                if next_attempt_at >= delivery.expires_at {
+                    tracing::warn!(error=?err, "Giving up on delivery, alert has expired");
+                    expired
+                } else {
+                    tracing::warn!(error=?err, next_attempt_at, "Transient error, will retry");
+                    DeliveryOutcome::Retry {
+                        next_attempt_at,
+                        error,
+                    }
+                }
+            }
+        };
+
+        Ok(outcome)
+    }
+
+    /// Sends the alert and writes the attempt to the delivery log
+    async fn deliver(&self, alert: &Alert, user: &Subscription, ttl: u32) -> Result<(), PushError> {
+        let (attempt, result) = self.attempt(alert, user, ttl).await;
+
+        // the log is informational, it shouldn't fail an otherwise good delivery
+        if let Err(err) = self.store.record_delivery_attempt(&attempt).await {
+            tracing::warn!(?err, "Couldn't record delivery attempt");
         }
 
-        Ok(())
+        result
     }
 
-    #[tracing::instrument(skip(self), err)]
+    /// Sends the alert over whichever channel the subscriber signed up with, `ttl` is how many
+    /// seconds the alert is still worth delivering for
+    async fn attempt(
+        &self,
+        alert: &Alert,
+        user: &Subscription,
+        ttl: u32,
+    ) -> (DeliveryAttempt, Result<(), PushError>) {
+        let attempted_at = Utc::now().timestamp();
+        let started = Instant::now();
+        let result = self.send_to_channel(alert, user, ttl).await;
+
+        let attempt = DeliveryAttempt {
+            endpoint: user.endpoint.clone(),
+            game_id: alert.game_id,
+            notification: alert.kind,
+            title: alert.title.clone(),
+            body: alert.body.clone(),
+            attempted_at,
+            status_code: match &result {
+                Ok(status) => Some(status.as_u16()),
+                Err(err) => err.status().map(|status| status.as_u16()),
+            },
+            latency_ms: u32::try_from(started.elapsed().as_millis()).unwrap_or(u32::MAX),
+            error: result.as_ref().err().map(ToString::to_string),
+        };
 
+        (attempt, result.map(|_| ()))
+    }
+
+    async fn send_to_channel(
+        &self,
+        alert: &Alert,
+        user: &Subscription,
+        ttl: u32,
+    ) -> Result<StatusCode, PushError> {
+        let result = match user.channel {
+            Channel::WebPush => return self.send_user_notification(alert, user, ttl).await,
+            Channel::Ntfy => self.ntfy.send(user, alert).await,
+            Channel::Gotify => self.gotify.send(user, alert).await,
+            Channel::Matrix => self.matrix.send(user, alert).await,
+        };
+
+        result.map_err(|err| {
+            if err.is_permanent() {
+                PushError::Expired(err.into(), user.endpoint.clone())
+            } else {
+                PushError::Other(err.into())
+            }
+        })
+    }
+
+    #[tracing::instrument(skip(self), err)]
     async fn send_user_notification(
         &self,
-        notification: &str,
-        user: Subscription,
-    ) -> Result<(), PushError> {
+        alert: &Alert,
+        user: &Subscription,
+        ttl: u32,
+    ) -> Result<StatusCode, PushError> {
+        let policy = alert
+            .kind
+            .map_or(TEST_POLICY, |kind| *self.policies.get(kind));
+        let collapse = policy.collapse;
+
+        // older clients show the payload as is, so they only get the text
+        let content = if user.payload_version == 0 {
+            alert.body.clone().into_bytes()
+        } else {
+            serde_json::to_vec(&Payload::new(alert, &user.teams, collapse))
+                .expect("payload should serialize")
+        };
+
         let endpoint = user.endpoint.clone();
         let subscription = SubscriptionInfo {
-            endpoint: user.endpoint,
+            endpoint: user.endpoint.clone(),
             keys: SubscriptionKeys {
-                p256dh: user.p256dh,
-                auth: user.auth,
+                p256dh: user.p256dh.clone(),
+                auth: user.auth.clone(),
             },
         };
 
         let signature = self
-            .sig_builder
-            .clone()
-            .add_sub_info(&subscription)
-            .build()
-            .unwrap();
+            .web_push
+            .sign(&subscription)
+            .map_err(|err| PushError::Undeliverable(err.into()))?;
 
         //Now add payload and encrypt.
         let mut builder = WebPushMessageBuilder::new(&subscription);
-        let content = notification.as_bytes();
-        builder.set_payload(ContentEncoding::Aes128Gcm, content);
+        builder.set_payload(ContentEncoding::Aes128Gcm, &content);
         builder.set_vapid_signature(signature);
-        builder.set_urgency(Urgency::High);
+        builder.set_urgency(policy.urgency.into());
+        builder.set_ttl(ttl);
+        if let (Collapse::Replace, Some(game_id), Some(kind)) =
+            (collapse, alert.game_id, alert.kind)
+        {
+            builder.set_topic(collapse.key(game_id, kind));
+        }
 
-        self.client
-            .send(builder.build().unwrap())
-            .map_err(|err| match err {
-                WebPushError::EndpointNotValid | WebPushError::EndpointNotFound => {
-                    PushError::Expired(err, endpoint)
-                }
-                err => PushError::Other(err),
-            })
-            .await
+        let message = builder
+            .build()
+            .map_err(|err| PushError::Undeliverable(err.into()))?;
+
+        self.web_push.send(message).await.map_err(|err| {
+            if push::is_expired(&err) {
+                PushError::Expired(err.into(), endpoint)
+            } else if push::is_rejected(&err) {
+                PushError::Undeliverable(err.into())
+            } else {
+                PushError::Other(err.into())
+            }
+        })
     }
 
     #[tracing::instrument(skip(self), err)]
@@ -233,15 +891,211 @@ impl Notifier {
         };
 
         let utc_now = chrono::Utc::now();
-        let aest_now = utc_now
-            .with_timezone(&chrono_tz::Australia::Melbourne)
+        let local_now = utc_now
+            .with_timezone(&subscription.timezone())
             .format("%Y-%m-%d %H:%M:%S %Z");
-        let notification = format!("Test notification from FootyAlerts ({aest_now})");
+        let body = format!("Test notification from FootyAlerts ({local_now})");
+
+        self.send_message(&subscription, body).await
+    }
+
+    /// Sends the one-time code that proves whoever asked for it can read the subscription's
+    /// notifications, for subscriptions with nothing else to prove ownership with
+    #[tracing::instrument(skip(self, subscription, code), fields(endpoint = subscription.endpoint), err)]
+    pub async fn send_claim_code(
+        &self,
+        subscription: &Subscription,
+        code: &str,
+    ) -> Result<(), Error> {
+        let body = format!("Your FootyAlerts claim code is {code}");
+
+        self.send_message(subscription, body).await
+    }
 
-        self.send_user_notification(&notification, subscription)
-            .await
-            .unwrap();
+    /// Sends a message about the site rather than a game, straight away
+    async fn send_message(&self, subscription: &Subscription, body: String) -> Result<(), Error> {
+        let alert = Alert {
+            title: String::from("Footy Alerts"),
+            body,
+            kind: None,
+            game_id: None,
+            scores: None,
+            url: String::from(SITE_URL),
+        };
 
-        Ok(())
+        let ttl = TEST_POLICY.ttl_seconds;
+        Ok(self.deliver(&alert, subscription, ttl).await?)
     }
+
+    /// Sends a season push straight to every active subscription it hasn't been sent to yet,
+    /// bypassing the outbox, with the alert for each from their templates. Failures aren't
+    /// retried, but subscriptions whose push service asks us to back off are left for a later
+    /// broadcast. Subscriptions are deactivated when their endpoint has expired.
+    #[tracing::instrument(skip(self, alert), ret, err)]
+    pub async fn broadcast<F>(
+        &self,
+        year: u16,
+        kind: SeasonEvent,
+        alert: F,
+    ) -> Result<Broadcast, Error>
+    where
+        F: Fn(&Subscription, &Templates) -> Alert + Sync,
+    {
+        let mut broadcast = Broadcast::default();
+        let mut after = String::new();
+
+        loop {
+            let subscriptions = self
+                .store
+                .get_season_event_recipients(year, kind, &after, self.fan_out.batch_size)
+                .await?;
+            let Some(last) = subscriptions.last() else {
+                return Ok(broadcast);
+            };
+            after.clone_from(&last.endpoint);
+
+            let results: Vec<_> = futures::stream::iter(subscriptions)
+                .map(|subscription| async {
+                    let _permit = self.throttle.acquire(&subscription.endpoint).await;
+                    let templates = self.templates.get(subscription.locale, subscription.style);
+                    let result = self
+                        .send_broadcast(&alert(&subscription, templates), &subscription)
+                        .await;
+                    (subscription, result)
+                })
+                .buffer_unordered(self.fan_out.concurrency)
+                .collect()
+                .await;
+
+            let mut sent = Vec::with_capacity(results.len());
+            for (subscription, result) in results {
+                match result {
+                    Ok(()) => broadcast.delivered += 1,
+                    // not sent, so picked up again next time
+                    Err(PushError::Deferred(_)) => {
+                        broadcast.deferred += 1;
+                        continue;
+                    }
+                    Err(PushError::Expired(..)) => {
+                        self.store
+                            .delete_subscription(&subscription.endpoint)
+                            .await?;
+                        broadcast.deactivated += 1;
+                    }
+                    Err(err) => {
+                        tracing::warn!(error = ?err, endpoint = subscription.endpoint, "Broadcast failed");
+                        broadcast.failed += 1;
+                    }
+                }
+                sent.push(subscription.endpoint);
+            }
+
+            self.store
+                .record_season_event_recipients(year, kind, &sent, Utc::now().timestamp())
+                .await?;
+        }
+    }
+
+    /// Sends one subscriber's part of a broadcast, unless their push service has asked us to
+    /// back off
+    async fn send_broadcast(&self, alert: &Alert, user: &Subscription) -> Result<(), PushError> {
+        if let Some(until) = self
+            .throttle
+            .blocked_until(&user.endpoint, Utc::now().timestamp())
+        {
+            return Err(PushError::Deferred(until));
+        }
+
+        match self.deliver(alert, user, BROADCAST_TTL_SECS).await {
+            Err(PushError::Other(err)) => match self.back_off(&user.endpoint, &err, 1) {
+                Some(until) => Err(PushError::Deferred(until)),
+                None => Err(PushError::Other(err)),
+            },
+            result => result,
+        }
+    }
+}
+
+/// How a [`Notifier::broadcast`] went
+#[derive(Debug, Default, PartialEq)]
+pub struct Broadcast {
+    pub delivered: u64,
+    pub deactivated: u64,
+    /// Couldn't be sent to, and won't be tried again
+    pub failed: u64,
+    /// Held up by their push service, to be tried again by a later broadcast
+    pub deferred: u64,
+}
+
+/// Seconds to wait before the next attempt, with jitter so retries from a big fan-out don't all
+/// land on the push service at once
+fn retry_delay(attempts: u32) -> i64 {
+    let exponent = i32::try_from(attempts.saturating_sub(1)).unwrap_or(i32::MAX);
+    let delay = (RETRY_BASE_SECS * 2f64.powi(exponent)).min(RETRY_MAX_SECS);
+    let jitter = rand::thread_rng().gen_range(0.5..1.5);
+
+    #[allow(clippy::cast_possible_truncation)]
+    let delay = (delay * jitter).round() as i64;
+    delay
+}
+
+/// Combines several alerts for one subscriber into a single one, e.g.
+/// "3/4 Time: GEE 64-52 HAW · COL 80-71 CARL"
+#[must_use]
+pub fn digest(notifications: &[Notification], templates: &Templates) -> Alert {
+    let labels: Vec<_> = notifications
+        .iter()
+        .map(|notification| notification.label(templates))
+        .collect();
+    let body = if labels.windows(2).all(|pair| pair[0] == pair[1]) {
+        let summaries: Vec<_> = notifications
+            .iter()
+            .map(|notification| notification.summary(templates))
+            .collect();
+        format!("{}: {}", labels[0], summaries.join(" · "))
+    } else {
+        let summaries: Vec<_> = notifications
+            .iter()
+            .zip(labels)
+            .map(|(notification, label)| format!("{label} {}", notification.summary(templates)))
+            .collect();
+        summaries.join(" · ")
+    };
+    let title = templates.digest_title.render(&Values {
+        count: Some(notifications.len()),
+        ..Values::default()
+    });
+
+    Alert {
+        title,
+        body,
+        kind: None,
+        game_id: None,
+        scores: None,
+        url: String::from(SITE_URL),
+    }
+}
+
+/// Groups deliveries so each web push subscriber gets one push for all their alerts. Other
+/// channels keep one message per alert, Matrix for one edits its message for each game.
+fn digests(deliveries: Vec<Delivery>) -> Vec<Vec<Delivery>> {
+    let mut groups: Vec<Vec<Delivery>> = Vec::new();
+    let mut web_push: HashMap<String, usize> = HashMap::new();
+
+    for delivery in deliveries {
+        if delivery.subscription.channel != Channel::WebPush {
+            groups.push(vec![delivery]);
+            continue;
+        }
+
+        match web_push.get(&delivery.subscription.endpoint) {
+            Some(index) => groups[*index].push(delivery),
+            None => {
+                web_push.insert(delivery.subscription.endpoint.clone(), groups.len());
+                groups.push(vec![delivery]);
+            }
+        }
+    }
+
+    groups
 }
@@ -0,0 +1,112 @@
+use serde::Deserialize;
+use squiggle::types::GameId;
+
+use crate::store::types::Notification;
+
+/// Whether a newer alert for a game replaces the older ones
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Collapse {
+    /// The alert is shown alongside earlier alerts for the game
+    Keep,
+    /// The alert replaces earlier alerts for the game, both at the push service (if the device
+    /// is offline) and on the device
+    Replace,
+}
+
+/// How urgently the push service should deliver the alert, a lower urgency lets a device that's
+/// saving battery wait before waking up for it
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Urgency {
+    VeryLow,
+    Low,
+    Normal,
+    High,
+}
+
+/// How alerts of a single kind are delivered
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Policy {
+    pub collapse: Collapse,
+    pub urgency: Urgency,
+    /// How long the push service holds on to an alert for a device that's offline
+    pub ttl_seconds: u32,
+}
+
+/// Delivery policy for each kind of alert. Configured as JSON, kinds that aren't given keep
+/// their default policy, e.g.
+/// `{"close_game": {"collapse": "replace", "urgency": "high", "ttl_seconds": 300}}`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Policies {
+    pub end_of_first_quarter: Policy,
+    pub end_of_second_quarter: Policy,
+    pub end_of_third_quarter: Policy,
+    pub end_of_game: Policy,
+    pub close_game: Policy,
+}
+
+impl Default for Policies {
+    fn default() -> Self {
+        // stale once the next quarter is well underway
+        let end_of_quarter = Policy {
+            collapse: Collapse::Replace,
+            urgency: Urgency::High,
+            ttl_seconds: 30 * 60,
+        };
+
+        Self {
+            end_of_first_quarter: end_of_quarter,
+            end_of_second_quarter: end_of_quarter,
+            end_of_third_quarter: end_of_quarter,
+            end_of_game: Policy {
+                collapse: Collapse::Replace,
+                urgency: Urgency::Normal,
+                ttl_seconds: 12 * 60 * 60,
+            },
+            // only worth knowing while there's still time to tune in
+            close_game: Policy {
+                collapse: Collapse::Replace,
+                urgency: Urgency::High,
+                ttl_seconds: 5 * 60,
+            },
+        }
+    }
+}
+
+impl Policies {
+    #[must_use]
+    pub fn get(&self, kind: Notification) -> &Policy {
+        match kind {
+            Notification::EndOfFirstQuarter => &self.end_of_first_quarter,
+            Notification::EndOfSecondQuarter => &self.end_of_second_quarter,
+            Notification::EndOfThirdQuarter => &self.end_of_third_quarter,
+            Notification::EndOfGame => &self.end_of_game,
+            Notification::CloseGame => &self.close_game,
+        }
+    }
+}
+
+impl Collapse {
+    /// Alerts with the same key replace each other on the device. Keys of replaceable alerts
+    /// are also used as the web push `Topic`, so must be at most 32 url safe base64 characters.
+    #[must_use]
+    pub fn key(self, game_id: GameId, kind: Notification) -> String {
+        match self {
+            Collapse::Keep => format!("game-{game_id}-{}", kind.name()),
+            Collapse::Replace => format!("game-{game_id}"),
+        }
+    }
+}
+
+impl From<Urgency> for web_push::Urgency {
+    fn from(value: Urgency) -> Self {
+        match value {
+            Urgency::VeryLow => web_push::Urgency::VeryLow,
+            Urgency::Low => web_push::Urgency::Low,
+            Urgency::Normal => web_push::Urgency::Normal,
+            Urgency::High => web_push::Urgency::High,
+        }
+    }
+}
@@ -7,8 +7,12 @@ use squiggle::{
 };
 
 use crate::{
+    mqtt::Publisher,
     notifier::{Notification, Notifier, Quarter},
-    store::{types::Game as DbGame, Store},
+    store::{
+        types::{Final, Game as DbGame, Locale, Style},
+        Store,
+    },
 };
 
 #[derive(Debug, thiserror::Error)]
@@ -27,6 +31,7 @@ pub struct Processor {
     store: Store,
     rest_client: Client,
     notifier: Notifier,
+    publisher: Option<Publisher>,
 }
 
 /// How complete the game needs to be before we send out close game alerts
@@ -84,25 +89,25 @@ pub fn maybe_notification(game: &Game) -> Option<Notification> {
 }
 
 #[tracing::instrument(ret)]
-fn patch_game_with_event(mut game: Game, event: Event) -> Game {
+fn patch_game_with_event(mut game: Game, event: &Event) -> Game {
     match event {
         Event::Score(score) => {
             game.away_score = score.score.away_score;
             game.home_score = score.score.home_score;
             game.complete = score.complete;
-            game.timestr = Some(score.timestr);
+            game.timestr = Some(score.timestr.clone());
         }
         Event::Game(_) => {
             // ignore for now as it's not that useful
         }
         Event::TimeStr(timestr) => {
-            game.timestr = Some(timestr.timestr);
+            game.timestr = Some(timestr.timestr.clone());
         }
         Event::Complete(complete) => {
             game.complete = complete.complete;
         }
         Event::Winner(winner) => {
-            game.winner = Some(winner.winner);
+            game.winner = Some(winner.winner.clone());
         }
     }
 
@@ -115,40 +120,57 @@ impl Processor {
             store,
             rest_client,
             notifier,
+            publisher: None,
         }
     }
+
+    /// Also publish every event and notification to MQTT
+    #[must_use]
+    pub fn with_publisher(self, publisher: Publisher) -> Self {
+        Self {
+            publisher: Some(publisher),
+            ..self
+        }
+    }
+
     #[tracing::instrument(skip(self), err)]
     pub async fn process_event(&self, event: Event) -> Result<(), Error> {
         let game_id = event.id();
         let db_game = self.get_or_insert_game(game_id).await?;
 
-        let game = patch_game_with_event(Game::try_from(db_game)?, event);
+        let game = patch_game_with_event(Game::try_from(db_game)?, &event);
         let maybe_notification = maybe_notification(&game);
 
         self.update_game(game.clone()).await?;
 
+        if let Some(publisher) = &self.publisher {
+            // publishing is best effort, it shouldn't stop alerts going out
+            let _ = publisher.publish_event(&game, &event);
+        }
+
         // see if we should send a notification
         let Some(notification) = maybe_notification else {
             return Ok(());
         };
 
-        let db_notification = crate::store::types::Notification::from(&notification);
-
-        // check if we've already sent a notification
-        if self
-            .store
-            .game_has_notification(game_id, db_notification)
-            .await?
-        {
+        // claiming the alert and queueing its deliveries happen together, so it's only sent once
+        // even if events for the game race, and a crash part way through sending is picked up
+        // from the outbox
+        let record = notification.to_record(game_id);
+        if self.notifier.record(&record).await?.is_none() {
             return Ok(());
         }
 
-        // mark the notification as sent
-        self.store
-            .record_notification(game_id, db_notification)
-            .await?;
+        if let Some(publisher) = &self.publisher {
+            let templates = self.notifier.templates(Locale::default(), Style::default());
+            let final_round = Final::new(game.is_final, game.is_grand_final != 0);
+            let _ = publisher.publish_alert(
+                &game,
+                &notification.to_alert(game_id, final_round, templates),
+            );
+        }
 
-        self.notifier.notify(game, notification).await?;
+        self.notifier.dispatch().await?;
 
         return Ok(());
     }
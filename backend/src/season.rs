@@ -0,0 +1,239 @@
+/// Pushes to every subscriber at either end of the season, worked out from the games we know of
+use chrono::{DateTime, Timelike, Utc};
+use squiggle::rest::{types::Game, Client};
+
+use crate::{
+    channel::Alert,
+    notifier::{game_start, Notifier, SITE_URL},
+    store::{
+        types::{Game as DbGame, SeasonEvent, Subscription, DEFAULT_TIMEZONE},
+        Store,
+    },
+    templates::{Templates, Values},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Squiggle API: {0}")]
+    SquiggleApi(#[from] squiggle::rest::Error),
+    #[error("Store: {0}")]
+    Store(#[from] crate::store::Error),
+    #[error("Notifier: {0}")]
+    Notifier(#[from] crate::notifier::Error),
+    #[error("Deser: {0}")]
+    Deser(#[from] serde_json::Error),
+}
+
+/// How long before the first bounce of the season subscribers are reminded we're back
+const SEASON_START_LEAD_DAYS: i64 = 3;
+
+/// Hours of the day, in Melbourne, that season pushes can go out
+const SEND_HOURS: std::ops::Range<u32> = 10..20;
+
+pub struct Season {
+    store: Store,
+    rest_client: Client,
+    notifier: Notifier,
+}
+
+impl Season {
+    pub fn new(store: Store, rest_client: Client, notifier: Notifier) -> Self {
+        Self {
+            store,
+            rest_client,
+            notifier,
+        }
+    }
+
+    /// Sends whichever season push is due, and looks for next season's fixture once this one
+    /// is over and everyone's been told
+    #[tracing::instrument(skip(self), err)]
+    pub async fn tick(&self, now: DateTime<Utc>) -> Result<(), Error> {
+        let Some(year) = self.store.get_latest_year().await? else {
+            return Ok(());
+        };
+
+        match self.store.get_grand_final(year).await? {
+            Some(grand_final) if grand_final.complete == 100 => {
+                self.season_over(&grand_final, now).await?;
+                // next year's fixture makes it the latest year, so not until everyone's had this
+                // one's push
+                if self
+                    .store
+                    .is_season_event_sent(year, SeasonEvent::End)
+                    .await?
+                {
+                    self.fetch_opening_round(year + 1).await?;
+                }
+                Ok(())
+            }
+            _ => self.season_starting(year, now).await,
+        }
+    }
+
+    async fn season_over(&self, grand_final: &DbGame, now: DateTime<Utc>) -> Result<(), Error> {
+        let year = grand_final.year;
+        if !sending_hours(now)
+            || self
+                .store
+                .is_season_event_sent(year, SeasonEvent::End)
+                .await?
+        {
+            return Ok(());
+        }
+
+        let broadcast = self
+            .notifier
+            .broadcast(year, SeasonEvent::End, |_, templates| {
+                season_over_alert(grand_final, templates)
+            })
+            .await?;
+        tracing::info!(year, ?broadcast, "Sent end of season push");
+        self.finish(year, SeasonEvent::End, broadcast.deferred, now)
+            .await
+    }
+
+    /// Reminds everyone we're back just before the first game, dropping anyone whose endpoint
+    /// has expired
+    async fn season_starting(&self, year: u16, now: DateTime<Utc>) -> Result<(), Error> {
+        let opening_round = self.store.get_opening_round(year).await?;
+        let Some(first_bounce) = opening_round
+            .iter()
+            .cloned()
+            .filter_map(|game| game_start(&Game::try_from(game).ok()?))
+            .min()
+        else {
+            return Ok(());
+        };
+
+        let lead = first_bounce - now;
+        if lead <= chrono::TimeDelta::zero()
+            || lead > chrono::TimeDelta::days(SEASON_START_LEAD_DAYS)
+            || !sending_hours(now)
+            || self
+                .store
+                .is_season_event_sent(year, SeasonEvent::Start)
+                .await?
+        {
+            return Ok(());
+        }
+
+        let broadcast = self
+            .notifier
+            .broadcast(year, SeasonEvent::Start, |subscription, templates| {
+                season_start_alert(year, first_bounce, &opening_round, subscription, templates)
+            })
+            .await?;
+        tracing::info!(year, ?broadcast, "Sent start of season push");
+        self.finish(year, SeasonEvent::Start, broadcast.deferred, now)
+            .await
+    }
+
+    /// Records the push as sent once everyone's had it, anyone whose push service held it up
+    /// gets it on a later tick
+    async fn finish(
+        &self,
+        year: u16,
+        kind: SeasonEvent,
+        deferred: u64,
+        now: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        if deferred == 0 {
+            self.store
+                .record_season_event(year, kind, now.timestamp())
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Squiggle has the fixture long before the season starts, Opening Round is round 0
+    async fn fetch_opening_round(&self, year: u16) -> Result<(), Error> {
+        if !self.store.get_opening_round(year).await?.is_empty() {
+            return Ok(());
+        }
+
+        let mut games = self.rest_client.fetch_games(0, year).await?;
+        if games.is_empty() {
+            games = self.rest_client.fetch_games(1, year).await?;
+        }
+        for game in games {
+            self.store.upsert_game(DbGame::try_from(game)?).await?;
+        }
+
+        Ok(())
+    }
+}
+
+fn sending_hours(now: DateTime<Utc>) -> bool {
+    SEND_HOURS.contains(&now.with_timezone(&DEFAULT_TIMEZONE).hour())
+}
+
+/// e.g. "Congratulations Sydney, 2024 premiers! That's the season, see you next year."
+fn season_over_alert(grand_final: &DbGame, templates: &Templates) -> Alert {
+    let premiers = match grand_final.home_score.cmp(&grand_final.away_score) {
+        std::cmp::Ordering::Greater => Some(&grand_final.home_team),
+        std::cmp::Ordering::Less => Some(&grand_final.away_team),
+        std::cmp::Ordering::Equal => None,
+    };
+    let values = Values {
+        year: Some(grand_final.year),
+        premiers: premiers.map(ToString::to_string),
+        ..Values::default()
+    };
+    let body = match premiers {
+        Some(_) => templates.season.over.render(&values),
+        None => templates.season.over_drawn.render(&values),
+    };
+
+    Alert {
+        title: String::from("Footy Alerts"),
+        body,
+        kind: None,
+        game_id: None,
+        scores: None,
+        url: String::from(SITE_URL),
+    }
+}
+
+/// e.g. "The 2025 season starts Thursday 6 March. Opening Round: SYD v HAW · GWS v COL", on
+/// the day it is where the subscriber is
+fn season_start_alert(
+    year: u16,
+    first_bounce: DateTime<Utc>,
+    opening_round: &[DbGame],
+    subscription: &Subscription,
+    templates: &Templates,
+) -> Alert {
+    let local = first_bounce.with_timezone(&subscription.timezone());
+    let fixture: Vec<_> = opening_round
+        .iter()
+        .map(|game| {
+            format!(
+                "{} v {}",
+                game.home_team.abbreviation(),
+                game.away_team.abbreviation()
+            )
+        })
+        .collect();
+    let values = Values {
+        year: Some(year),
+        date: Some(templates.season.date(local.date_naive())),
+        round: Some(
+            templates
+                .season
+                .round(opening_round.first().map_or(1, |game| game.round)),
+        ),
+        fixture: Some(fixture.join(" · ")),
+        ..Values::default()
+    };
+
+    Alert {
+        title: String::from("Footy Alerts"),
+        body: templates.season.start.render(&values),
+        kind: None,
+        game_id: None,
+        scores: None,
+        url: String::from(SITE_URL),
+    }
+}
@@ -4,8 +4,11 @@ use std::collections::HashMap;
 
 use serde::Serialize;
 use sqlx::{migrate::MigrateError, SqlitePool};
-use squiggle::types::{GameId, Team};
-use types::{Game, Notification, Subscription};
+use squiggle::types::GameId;
+use types::{
+    Alert, Delivery, DeliveryAttempt, DeliveryOutcome, DeliveryStatus, Game, GameFollow,
+    Notification, Scope, SeasonEvent, Snooze, Subscription,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum InitError {
@@ -46,6 +49,82 @@ struct DomainCount {
     domain: String,
     subscriptions_count: u32,
 }
+
+fn insert_delivery_attempt(
+    attempt: &DeliveryAttempt,
+) -> sqlx::query::Query<'_, sqlx::Sqlite, sqlx::sqlite::SqliteArguments<'_>> {
+    sqlx::query(
+        r"
+        INSERT INTO delivery_log (endpoint, game_id, notification, title, body, attempted_at,
+                                  status_code, latency_ms, error)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ",
+    )
+    .bind(&attempt.endpoint)
+    .bind(attempt.game_id)
+    .bind(attempt.notification)
+    .bind(&attempt.title)
+    .bind(&attempt.body)
+    .bind(attempt.attempted_at)
+    .bind(attempt.status_code)
+    .bind(attempt.latency_ms)
+    .bind(&attempt.error)
+}
+
+/// Everything in `subscriptions`, plus the teams followed as a JSON array for
+/// [`Subscription::teams`]
+const SUBSCRIPTION_COLUMNS: &str = r"
+    subscriptions.*,
+    (SELECT json_group_array(team) FROM subscription_teams
+     WHERE subscription_teams.endpoint = subscriptions.endpoint) AS teams
+";
+
+/// Whether a subscription is still snoozed for a game, binds the game id then the current time
+const SNOOZED: &str = r"
+    ((snoozed_year IS NOT NULL
+      AND NOT EXISTS (SELECT 1 FROM games
+                      WHERE games.id = ?
+                        AND (games.year > snoozed_year
+                             OR (games.year = snoozed_year
+                                 AND IIF(snoozed_round IS NULL, games.is_final != 0,
+                                         games.round >= snoozed_round)))))
+     OR IFNULL(snoozed_until, 0) > ?)
+";
+
+/// Conditions on `subscriptions` for who should get a notification, binds the home and away
+/// team, the game id for [`Scope`], the game id again, then the game id and the current time
+/// for [`SNOOZED`]
+fn subscription_filter(notification: Notification) -> String {
+    // games with a followed team get `notifications`, other games `other_notifications`, unless
+    // no teams are followed and every game is followed. Either way only games in the
+    // subscription's scope. Games followed on their own have their own preferences on top of
+    // those.
+    format!(
+        r"
+        (active = 1)
+        AND ((IIF(EXISTS (SELECT 1 FROM subscription_teams
+                          WHERE subscription_teams.endpoint = subscriptions.endpoint
+                            AND subscription_teams.team IN (?, ?))
+                  OR NOT EXISTS (SELECT 1 FROM subscription_teams
+                                 WHERE subscription_teams.endpoint = subscriptions.endpoint),
+                  notifications, other_notifications) & {bit} != 0
+              AND (scope = {all}
+                   OR EXISTS (SELECT 1 FROM games
+                              WHERE games.id = ?
+                                AND IIF(scope = {finals}, games.is_final != 0,
+                                        games.is_grand_final))))
+             OR EXISTS (SELECT 1 FROM subscription_games
+                        WHERE subscription_games.endpoint = subscriptions.endpoint
+                          AND subscription_games.game_id = ?
+                          AND subscription_games.notifications & {bit} != 0))
+        AND NOT {SNOOZED}
+        ",
+        bit = notification.bit(),
+        all = Scope::All as u8,
+        finals = Scope::Finals as u8,
+    )
+}
+
 impl Store {
     pub async fn new(url: &str) -> Result<Self, InitError> {
         let pool = SqlitePool::connect(url).await?;
@@ -64,8 +143,8 @@ impl Store {
 
         let game: Game = sqlx::query_as(
             r"
-            INSERT OR REPLACE INTO games (id, round, complete, home_team, away_team, home_score, away_score, timestr, year, date, tz)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            INSERT OR REPLACE INTO games (id, round, complete, home_team, away_team, home_score, away_score, timestr, year, date, tz, is_final, is_grand_final)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             RETURNING *
             ",
         )
@@ -80,6 +159,8 @@ impl Store {
             .bind(game.year)
             .bind(game.date)
             .bind(game.tz)
+            .bind(game.is_final)
+            .bind(game.is_grand_final)
             .fetch_one(&mut *transaction)
             .await?;
 
@@ -104,17 +185,26 @@ impl Store {
         Ok(game)
     }
 
+    /// Games in the latest round that's under way, or the first one to come if none have
+    /// started. Finals weeks are rounds too, so this is the week's finals, and the Grand Final
+    /// until next season's Opening Round bounces.
     #[tracing::instrument(skip(self), ret, err)]
     pub async fn get_this_round_games(&self) -> Result<Vec<Game>, Error> {
         let mut conn = self.pool.acquire().await?;
 
         let games: Vec<Game> = sqlx::query_as(
             r"
-            SELECT *
-            FROM games
-            WHERE year = (SELECT MAX(year) FROM games)
-              AND round = (SELECT MAX(round) FROM games WHERE year = (SELECT MAX(year) FROM games));
-           ",
+            WITH this_round AS (
+                SELECT year, round FROM games
+                ORDER BY complete > 0 DESC,
+                         IIF(complete > 0, -year, year),
+                         IIF(complete > 0, -round, round)
+                LIMIT 1
+            )
+            SELECT games.*
+            FROM games JOIN this_round USING (year, round)
+            ORDER BY date, id
+            ",
         )
         .fetch_all(&mut *conn)
         .await?;
@@ -122,70 +212,491 @@ impl Store {
         Ok(games)
     }
 
+    /// The latest year with any games, including next season's once its fixture is out
     #[tracing::instrument(skip(self), ret, err)]
-    pub async fn game_has_notification(
-        &self,
-        game: GameId,
-        notification: Notification,
-    ) -> Result<bool, Error> {
+    pub async fn get_latest_year(&self) -> Result<Option<u16>, Error> {
         let mut conn = self.pool.acquire().await?;
 
-        let rows = sqlx::query(
+        let year = sqlx::query_scalar("SELECT MAX(year) FROM games")
+            .fetch_one(&mut *conn)
+            .await?;
+
+        Ok(year)
+    }
+
+    /// The year's Grand Final, once it's known
+    #[tracing::instrument(skip(self), ret, err)]
+    pub async fn get_grand_final(&self, year: u16) -> Result<Option<Game>, Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        let game = sqlx::query_as(
             r"
-            SELECT notification FROM alerts WHERE id = ? and notification = ?
+            SELECT * FROM games WHERE year = ? AND is_grand_final
             ",
         )
-        .bind(game)
-        .bind(notification as u8)
+        .bind(year)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        Ok(game)
+    }
+
+    /// Games in the first round of the year that we know of
+    #[tracing::instrument(skip(self), ret, err)]
+    pub async fn get_opening_round(&self, year: u16) -> Result<Vec<Game>, Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        let games = sqlx::query_as(
+            r"
+            SELECT * FROM games
+            WHERE year = ?
+              AND round = (SELECT MIN(round) FROM games WHERE year = ?)
+            ORDER BY date, id
+            ",
+        )
+        .bind(year)
+        .bind(year)
         .fetch_all(&mut *conn)
         .await?;
 
-        Ok(!rows.is_empty())
+        Ok(games)
     }
 
+    /// Whether the push for the year has been sent to everyone
+    #[tracing::instrument(skip(self), ret, err)]
+    pub async fn is_season_event_sent(&self, year: u16, kind: SeasonEvent) -> Result<bool, Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        let sent = sqlx::query("SELECT 1 FROM season_events WHERE year = ? AND kind = ?")
+            .bind(year)
+            .bind(kind)
+            .fetch_optional(&mut *conn)
+            .await?
+            .is_some();
+
+        Ok(sent)
+    }
+
+    /// Records the push for the year as sent to everyone, so it isn't sent again
     #[tracing::instrument(skip(self), err)]
-    pub async fn record_notification(
+    pub async fn record_season_event(
         &self,
-        game: GameId,
-        notification: Notification,
+        year: u16,
+        kind: SeasonEvent,
+        now: i64,
+    ) -> Result<(), Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        sqlx::query("INSERT OR IGNORE INTO season_events (year, kind, sent_at) VALUES (?, ?, ?)")
+            .bind(year)
+            .bind(kind)
+            .bind(now)
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// A page of the active subscriptions the push for the year hasn't been sent to yet, in
+    /// order of endpoint, starting after `after`
+    #[tracing::instrument(skip(self), err)]
+    pub async fn get_season_event_recipients(
+        &self,
+        year: u16,
+        kind: SeasonEvent,
+        after: &str,
+        limit: u32,
+    ) -> Result<Vec<Subscription>, Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        let query = format!(
+            r"
+            SELECT {SUBSCRIPTION_COLUMNS} FROM subscriptions
+            WHERE active = 1
+              AND endpoint > ?
+              AND NOT EXISTS (SELECT 1 FROM season_event_recipients
+                              WHERE year = ? AND kind = ?
+                                AND season_event_recipients.endpoint = subscriptions.endpoint)
+            ORDER BY endpoint
+            LIMIT ?
+            "
+        );
+        let subscriptions = sqlx::query_as(&query)
+            .bind(after)
+            .bind(year)
+            .bind(kind)
+            .bind(limit)
+            .fetch_all(&mut *conn)
+            .await?;
+
+        Ok(subscriptions)
+    }
+
+    /// Records the push for the year as sent to the endpoints, in one transaction
+    #[tracing::instrument(skip(self, endpoints), err)]
+    pub async fn record_season_event_recipients(
+        &self,
+        year: u16,
+        kind: SeasonEvent,
+        endpoints: &[String],
+        now: i64,
     ) -> Result<(), Error> {
+        let mut transaction = self.pool.begin().await?;
+
+        for endpoint in endpoints {
+            sqlx::query(
+                r"
+                INSERT OR IGNORE INTO season_event_recipients (year, kind, endpoint, sent_at)
+                VALUES (?, ?, ?, ?)
+                ",
+            )
+            .bind(year)
+            .bind(kind)
+            .bind(endpoint)
+            .bind(now)
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// Records the alert and queues a pending delivery for everyone subscribed to it, in one
+    /// transaction. Returns how many deliveries were queued, or `None` if the alert had already
+    /// been recorded (e.g. by a concurrent event for the same game), in which case nothing is
+    /// queued. Once this returns the outbox owns the fan-out, so a restart picks up wherever
+    /// sending got to. Deliveries aren't due until `send_at`.
+    #[tracing::instrument(skip(self), ret, err)]
+    pub async fn record_alert(
+        &self,
+        alert: &Alert,
+        now: i64,
+        send_at: i64,
+        expires_at: i64,
+    ) -> Result<Option<u64>, Error> {
+        let mut transaction = self.pool.begin().await?;
+
+        let alert_id: Option<i64> = sqlx::query_scalar(
+            r"
+            INSERT INTO alerts (id, notification, home_team, away_team, home_score, away_score,
+                                timestr, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (id, notification) DO NOTHING
+            RETURNING alert_id
+            ",
+        )
+        .bind(alert.game_id)
+        .bind(alert.notification as u8)
+        .bind(&alert.home_team)
+        .bind(&alert.away_team)
+        .bind(alert.home_score)
+        .bind(alert.away_score)
+        .bind(&alert.timestr)
+        .bind(now)
+        .fetch_optional(&mut *transaction)
+        .await?;
+
+        let Some(alert_id) = alert_id else {
+            return Ok(None);
+        };
+
+        let query = format!(
+            r"
+            INSERT INTO outbox (alert_id, endpoint, status, next_attempt_at, expires_at,
+                                created_at)
+            SELECT ?, endpoint, IIF(hold_alerts, ?, ?), ? + IFNULL(spoiler_delay, 0),
+                   ? + IFNULL(spoiler_delay, 0), ?
+            FROM subscriptions WHERE {}
+            ",
+            subscription_filter(alert.notification)
+        );
+
+        let result = sqlx::query(&query)
+            .bind(alert_id)
+            .bind(DeliveryStatus::Held)
+            .bind(DeliveryStatus::Pending)
+            .bind(send_at)
+            .bind(expires_at)
+            .bind(now)
+            .bind(&alert.home_team)
+            .bind(&alert.away_team)
+            .bind(alert.game_id)
+            .bind(alert.game_id)
+            .bind(alert.game_id)
+            .bind(now)
+            .execute(&mut *transaction)
+            .await?;
+
+        // snoozes this game has outlasted are over
+        sqlx::query(&format!(
+            r"
+            UPDATE subscriptions
+            SET snoozed_until = NULL, snoozed_year = NULL, snoozed_round = NULL
+            WHERE (snoozed_until IS NOT NULL OR snoozed_year IS NOT NULL) AND NOT {SNOOZED}
+            "
+        ))
+        .bind(alert.game_id)
+        .bind(now)
+        .execute(&mut *transaction)
+        .await?;
+
+        // following a single game ends at full time
+        if alert.notification == Notification::EndOfGame {
+            sqlx::query("DELETE FROM subscription_games WHERE game_id = ?")
+                .bind(alert.game_id)
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(Some(result.rows_affected()))
+    }
+
+    /// Claims up to `limit` deliveries that are due, so no one else sends them until
+    /// `lease_until`. If a claimed delivery is never completed or rescheduled (e.g. the process
+    /// died while sending) it's picked up again once the lease runs out. Any other fresh
+    /// deliveries to the same subscribers that are due by `digest_until` are claimed too, so
+    /// they can be sent as one digest.
+    #[tracing::instrument(skip(self), err)]
+    pub async fn claim_deliveries(
+        &self,
+        now: i64,
+        digest_until: i64,
+        lease_until: i64,
+        limit: u32,
+    ) -> Result<Vec<Delivery>, Error> {
         let mut conn = self.pool.acquire().await?;
+        let claim: i64 = rand::random();
 
         sqlx::query(
             r"
-            INSERT INTO alerts (id, notification)
-            VALUES (?, ?)
+            WITH due AS (
+                SELECT id, endpoint FROM outbox
+                WHERE status = ? AND next_attempt_at <= ?
+                ORDER BY next_attempt_at
+                LIMIT ?
+            )
+            UPDATE outbox
+            SET claim = ?, next_attempt_at = ?, attempts = attempts + 1
+            WHERE id IN (SELECT id FROM due)
+               -- alerts still waiting out the digest window go with them, retries keep backing off
+               OR (status = ? AND attempts = 0 AND next_attempt_at <= ?
+                   AND endpoint IN (SELECT endpoint FROM due))
+            ",
+        )
+        .bind(DeliveryStatus::Pending)
+        .bind(now)
+        .bind(limit)
+        .bind(claim)
+        .bind(lease_until)
+        .bind(DeliveryStatus::Pending)
+        .bind(digest_until)
+        .execute(&mut *conn)
+        .await?;
+
+        let query = format!(
+            r"
+            SELECT outbox.id, outbox.attempts, outbox.expires_at,
+                   alerts.id AS game_id, alerts.notification, alerts.home_team, alerts.away_team,
+                   alerts.home_score, alerts.away_score, alerts.timestr,
+                   IFNULL(games.is_final, 0) AS is_final,
+                   IFNULL(games.is_grand_final, 0) AS is_grand_final,
+                   {SUBSCRIPTION_COLUMNS}
+            FROM outbox
+            JOIN alerts ON alerts.alert_id = outbox.alert_id
+            LEFT JOIN games ON games.id = alerts.id
+            JOIN subscriptions ON subscriptions.endpoint = outbox.endpoint
+            WHERE outbox.claim = ?
+            ORDER BY outbox.id
+            "
+        );
+        let deliveries: Vec<Delivery> = sqlx::query_as(&query)
+            .bind(claim)
+            .fetch_all(&mut *conn)
+            .await?;
+
+        Ok(deliveries)
+    }
+
+    /// Makes a subscriber's held deliveries due now, for one game or all of them. They get as
+    /// long to be delivered from now as they originally had. Returns how many were released.
+    #[tracing::instrument(skip(self), err)]
+    pub async fn release_held_deliveries(
+        &self,
+        endpoint: &str,
+        game: Option<GameId>,
+        now: i64,
+    ) -> Result<u64, Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        let result = sqlx::query(
+            r"
+            UPDATE outbox
+            SET status = ?, next_attempt_at = ?, expires_at = ? + expires_at - created_at
+            WHERE status = ? AND endpoint = ?
+              AND (? IS NULL OR alert_id IN (SELECT alert_id FROM alerts WHERE id = ?))
             ",
         )
+        .bind(DeliveryStatus::Pending)
+        .bind(now)
+        .bind(now)
+        .bind(DeliveryStatus::Held)
+        .bind(endpoint)
+        .bind(game)
         .bind(game)
-        .bind(notification as u8)
         .execute(&mut *conn)
         .await?;
 
-        Ok(())
+        Ok(result.rows_affected())
     }
 
+    /// Gives up on any pending deliveries whose alert is no longer worth sending, and any held
+    /// since before `held_before`
     #[tracing::instrument(skip(self), err)]
-    pub async fn add_subscription(&self, subscription: Subscription) -> Result<(), Error> {
+    pub async fn expire_deliveries(&self, now: i64, held_before: i64) -> Result<u64, Error> {
         let mut conn = self.pool.acquire().await?;
 
+        let result = sqlx::query(
+            r"
+            UPDATE outbox
+            SET status = ?, completed_at = ?, claim = NULL
+            WHERE (status = ? AND expires_at <= ?)
+               OR (status = ? AND created_at < ?)
+            ",
+        )
+        .bind(DeliveryStatus::Expired)
+        .bind(now)
+        .bind(DeliveryStatus::Pending)
+        .bind(now)
+        .bind(DeliveryStatus::Held)
+        .bind(held_before)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Records what happened to a page of claimed deliveries, along with the attempts made, in
+    /// one transaction
+    #[tracing::instrument(skip_all, fields(outcomes = outcomes.len()), err)]
+    pub async fn record_outcomes(
+        &self,
+        outcomes: &[(i64, DeliveryOutcome)],
+        attempts: &[DeliveryAttempt],
+        now: i64,
+    ) -> Result<(), Error> {
+        let mut transaction = self.pool.begin().await?;
+
+        for (id, outcome) in outcomes {
+            let query = match outcome {
+                DeliveryOutcome::Completed { status, error } => sqlx::query(
+                    r"
+                    UPDATE outbox
+                    SET status = ?, last_error = ?, completed_at = ?, claim = NULL
+                    WHERE id = ?
+                    ",
+                )
+                .bind(*status)
+                .bind(error)
+                .bind(now),
+                DeliveryOutcome::Retry {
+                    next_attempt_at,
+                    error,
+                } => sqlx::query(
+                    r"
+                    UPDATE outbox
+                    SET next_attempt_at = ?, last_error = ?, claim = NULL
+                    WHERE id = ?
+                    ",
+                )
+                .bind(*next_attempt_at)
+                .bind(error),
+                // not an attempt, so it shouldn't count as one
+                DeliveryOutcome::Deferred { next_attempt_at } => sqlx::query(
+                    r"
+                    UPDATE outbox
+                    SET next_attempt_at = ?, attempts = attempts - 1, claim = NULL
+                    WHERE id = ?
+                    ",
+                )
+                .bind(*next_attempt_at),
+            };
+
+            query.bind(id).execute(&mut *transaction).await?;
+        }
+
+        for attempt in attempts {
+            insert_delivery_attempt(attempt)
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        skip(self, subscription),
+        fields(endpoint = subscription.endpoint, channel = ?subscription.channel),
+        err
+    )]
+    pub async fn add_subscription(&self, subscription: Subscription) -> Result<(), Error> {
+        let mut transaction = self.pool.begin().await?;
+
         sqlx::query(
             r"
-            INSERT OR REPLACE INTO subscriptions (team, close_games, final_scores,
-                            quarter_scores, endpoint, p256dh, auth)
-            VALUES (?, ?, ?, ?, ?, ?, ?)
+            INSERT OR REPLACE INTO subscriptions (notifications, other_notifications, endpoint,
+                            p256dh, auth, channel, token, payload_version, timezone,
+                            quiet_hours_start, quiet_hours_end, spoiler_delay, hold_alerts,
+                            locale, style, scope, active, management_token_hash,
+                            superseded_by, snoozed_until, snoozed_year, snoozed_round)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             ",
         )
-        .bind(subscription.team)
-        .bind(subscription.close_games)
-        .bind(subscription.final_scores)
-        .bind(subscription.quarter_scores)
-        .bind(subscription.endpoint)
+        .bind(subscription.notifications)
+        .bind(subscription.other_notifications)
+        .bind(&subscription.endpoint)
         .bind(subscription.p256dh)
         .bind(subscription.auth)
-        .execute(&mut *conn)
+        .bind(subscription.channel)
+        .bind(subscription.token)
+        .bind(subscription.payload_version)
+        .bind(subscription.timezone)
+        .bind(subscription.quiet_hours_start)
+        .bind(subscription.quiet_hours_end)
+        .bind(subscription.spoiler_delay)
+        .bind(subscription.hold_alerts)
+        .bind(subscription.locale)
+        .bind(subscription.style)
+        .bind(subscription.scope)
+        .bind(subscription.active)
+        .bind(subscription.management_token_hash)
+        .bind(subscription.superseded_by)
+        .bind(subscription.snoozed_until)
+        .bind(subscription.snoozed_year)
+        .bind(subscription.snoozed_round)
+        .execute(&mut *transaction)
         .await?;
 
+        sqlx::query("DELETE FROM subscription_teams WHERE endpoint = ?")
+            .bind(&subscription.endpoint)
+            .execute(&mut *transaction)
+            .await?;
+
+        for team in subscription.teams {
+            sqlx::query("INSERT OR IGNORE INTO subscription_teams (endpoint, team) VALUES (?, ?)")
+                .bind(&subscription.endpoint)
+                .bind(team)
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        transaction.commit().await?;
+
         Ok(())
     }
 
@@ -196,87 +707,398 @@ impl Store {
     ) -> Result<Option<Subscription>, Error> {
         let mut conn = self.pool.acquire().await?;
 
-        let subscription: Option<Subscription> = sqlx::query_as(
+        let query = format!("SELECT {SUBSCRIPTION_COLUMNS} FROM subscriptions where endpoint = ?");
+        let subscription: Option<Subscription> = sqlx::query_as(&query)
+            .bind(endpoint)
+            .fetch_optional(&mut *conn)
+            .await?;
+
+        Ok(subscription)
+    }
+
+    /// Follows a single game, replacing any preferences it was already followed with. Returns
+    /// false if there's no such subscription, or the game is already over.
+    #[tracing::instrument(skip(self), err)]
+    pub async fn follow_game(&self, endpoint: &str, follow: &GameFollow) -> Result<bool, Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        let result = sqlx::query(
             r"
-            SELECT * FROM subscriptions where endpoint = ?
-           ",
+            INSERT OR REPLACE INTO subscription_games (endpoint, game_id, notifications)
+            SELECT endpoint, ?, ? FROM subscriptions
+            WHERE endpoint = ?
+              AND NOT EXISTS (SELECT 1 FROM games WHERE id = ? AND complete = 100)
+            ",
         )
+        .bind(follow.game_id)
+        .bind(follow.notifications)
         .bind(endpoint)
-        .fetch_optional(&mut *conn)
+        .bind(follow.game_id)
+        .execute(&mut *conn)
         .await?;
 
-        Ok(subscription)
+        Ok(result.rows_affected() > 0)
     }
 
+    /// Returns false if the game wasn't being followed
     #[tracing::instrument(skip(self), err)]
-    pub async fn get_subscriptions_for_notification(
+    pub async fn unfollow_game(&self, endpoint: &str, game_id: GameId) -> Result<bool, Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        let result =
+            sqlx::query("DELETE FROM subscription_games WHERE endpoint = ? AND game_id = ?")
+                .bind(endpoint)
+                .bind(game_id)
+                .execute(&mut *conn)
+                .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[tracing::instrument(skip(self), err)]
+    pub async fn get_game_follows(&self, endpoint: &str) -> Result<Vec<GameFollow>, Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        let follows = sqlx::query_as(
+            r"
+            SELECT game_id, notifications FROM subscription_games
+            WHERE endpoint = ?
+            ORDER BY game_id
+            ",
+        )
+        .bind(endpoint)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        Ok(follows)
+    }
+
+    /// Snoozes a subscription, or lifts its snooze. Returns false if there's no such
+    /// subscription.
+    #[tracing::instrument(skip(self), err)]
+    pub async fn snooze_subscription(
         &self,
-        home_team: Team,
-        away_team: Team,
-        notification: Notification,
-    ) -> Result<Vec<Subscription>, Error> {
+        endpoint: &str,
+        snooze: Option<Snooze>,
+    ) -> Result<bool, Error> {
         let mut conn = self.pool.acquire().await?;
 
-        // there's probably a nicer way to do this..
-        let mut query = String::from(
+        let (until, year, round) = Snooze::columns(snooze);
+        let result = sqlx::query(
             r"
-            SELECT * FROM subscriptions
-            WHERE (team = ? OR team = ? OR team IS NULL) AND (active = 1)
-           ",
-        );
+            UPDATE subscriptions
+            SET snoozed_until = ?, snoozed_year = ?, snoozed_round = ?
+            WHERE endpoint = ?
+            ",
+        )
+        .bind(until)
+        .bind(year)
+        .bind(round)
+        .bind(endpoint)
+        .execute(&mut *conn)
+        .await?;
 
-        let mut where_clause = vec![];
+        Ok(result.rows_affected() > 0)
+    }
 
-        if notification.is_close_game_notification() {
-            where_clause.push(String::from("close_games = 1"))
-        }
+    /// Stops sending alerts to a subscription, keeping its preferences and history. Alerts still
+    /// waiting to be sent to it are dropped. Returns false if there's no such subscription.
+    #[tracing::instrument(skip(self), err)]
+    pub async fn delete_subscription(&self, endpoint: &str) -> Result<bool, Error> {
+        let mut transaction = self.pool.begin().await?;
+
+        let result = sqlx::query(
+            r"
+            UPDATE subscriptions
+            SET active = 0
+            WHERE endpoint = ?
+            ",
+        )
+        .bind(endpoint)
+        .execute(&mut *transaction)
+        .await?;
 
-        if notification.is_quarter_notification() {
-            where_clause.push(String::from("quarter_scores = 1"))
+        sqlx::query(
+            r"
+            UPDATE outbox
+            SET status = ?, last_error = 'Unsubscribed', claim = NULL
+            WHERE endpoint = ? AND status IN (?, ?)
+            ",
+        )
+        .bind(DeliveryStatus::Failed)
+        .bind(endpoint)
+        .bind(DeliveryStatus::Pending)
+        .bind(DeliveryStatus::Held)
+        .execute(&mut *transaction)
+        .await?;
+
+        transaction.commit().await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Moves a web push subscription's preferences and history to the endpoint the browser
+    /// replaced it with, replacing anything already stored for the new endpoint, so callers need
+    /// to check that belongs to the same owner. The old subscription is kept, inactive and
+    /// superseded. Returns false if there's no such subscription, or it's already been
+    /// superseded.
+    #[tracing::instrument(skip(self, p256dh, auth), err)]
+    pub async fn supersede_subscription(
+        &self,
+        old_endpoint: &str,
+        endpoint: &str,
+        p256dh: &str,
+        auth: &str,
+    ) -> Result<bool, Error> {
+        let mut transaction = self.pool.begin().await?;
+
+        let result = sqlx::query(
+            r"
+            INSERT OR REPLACE INTO subscriptions (notifications, other_notifications, endpoint,
+                            p256dh, auth, channel, token, payload_version, timezone,
+                            quiet_hours_start, quiet_hours_end, spoiler_delay, hold_alerts,
+                            locale, style, scope, active, management_token_hash,
+                            superseded_by, snoozed_until, snoozed_year, snoozed_round)
+            SELECT notifications, other_notifications, ?, ?, ?, channel, token, payload_version,
+                   timezone, quiet_hours_start, quiet_hours_end, spoiler_delay, hold_alerts,
+                   locale, style, scope, active, management_token_hash, NULL, snoozed_until,
+                   snoozed_year, snoozed_round
+            FROM subscriptions
+            WHERE endpoint = ? AND superseded_by IS NULL
+            ",
+        )
+        .bind(endpoint)
+        .bind(p256dh)
+        .bind(auth)
+        .bind(old_endpoint)
+        .execute(&mut *transaction)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
         }
 
-        if notification.is_full_game_notification() {
-            where_clause.push(String::from("final_scores = 1"))
+        // the old endpoint's preferences win over anything the new one was given
+        for table in ["subscription_teams", "subscription_games"] {
+            sqlx::query(&format!("DELETE FROM {table} WHERE endpoint = ?"))
+                .bind(endpoint)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        for table in [
+            "subscription_teams",
+            "subscription_games",
+            "outbox",
+            "delivery_log",
+            "matrix_messages",
+            "season_event_recipients",
+        ] {
+            sqlx::query(&format!(
+                "UPDATE OR REPLACE {table} SET endpoint = ? WHERE endpoint = ?"
+            ))
+            .bind(endpoint)
+            .bind(old_endpoint)
+            .execute(&mut *transaction)
+            .await?;
         }
 
-        let where_str = where_clause.join(" OR ");
+        sqlx::query(
+            r"
+            UPDATE subscriptions
+            SET active = 0, superseded_by = ?
+            WHERE endpoint = ?
+            ",
+        )
+        .bind(endpoint)
+        .bind(old_endpoint)
+        .execute(&mut *transaction)
+        .await?;
 
-        if !where_str.is_empty() {
-            query.push_str("AND (");
-            query.push_str(&where_str);
-            query.push(')');
+        transaction.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Removes everything stored about a subscription. Returns false if there's no such
+    /// subscription.
+    #[tracing::instrument(skip(self), err)]
+    pub async fn purge_subscription(&self, endpoint: &str) -> Result<bool, Error> {
+        let mut transaction = self.pool.begin().await?;
+
+        for table in [
+            "subscription_teams",
+            "subscription_games",
+            "outbox",
+            "delivery_log",
+            "matrix_messages",
+            "subscription_claims",
+            "season_event_recipients",
+        ] {
+            sqlx::query(&format!("DELETE FROM {table} WHERE endpoint = ?"))
+                .bind(endpoint)
+                .execute(&mut *transaction)
+                .await?;
         }
 
-        let subscriptions: Vec<Subscription> = sqlx::query_as(&query)
-            .bind(home_team)
-            .bind(away_team)
-            .bind(notification.is_close_game_notification())
-            .bind(notification.is_full_game_notification())
-            .bind(notification.is_quarter_notification())
-            .fetch_all(&mut *conn)
+        let result = sqlx::query("DELETE FROM subscriptions WHERE endpoint = ?")
+            .bind(endpoint)
+            .execute(&mut *transaction)
             .await?;
 
-        Ok(subscriptions)
+        transaction.commit().await?;
+
+        Ok(result.rows_affected() > 0)
     }
 
+    /// Records a one-time code for claiming a subscription with, unless an earlier one hasn't
+    /// expired yet. Returns whether it was recorded, and so needs sending.
+    #[tracing::instrument(skip(self, code_hash), ret, err)]
+    pub async fn start_claim(
+        &self,
+        endpoint: &str,
+        code_hash: &str,
+        now: i64,
+        expires_at: i64,
+    ) -> Result<bool, Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        let result = sqlx::query(
+            r"
+            INSERT INTO subscription_claims (endpoint, code_hash, expires_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT (endpoint) DO UPDATE
+            SET code_hash = excluded.code_hash, expires_at = excluded.expires_at
+            WHERE subscription_claims.expires_at <= ?
+            ",
+        )
+        .bind(endpoint)
+        .bind(code_hash)
+        .bind(expires_at)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Forgets a subscription's claim code, e.g. when it couldn't be sent
     #[tracing::instrument(skip(self), err)]
-    pub async fn delete_subscription(&self, endpoint: &str) -> Result<(), Error> {
+    pub async fn cancel_claim(&self, endpoint: &str) -> Result<(), Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        sqlx::query("DELETE FROM subscription_claims WHERE endpoint = ?")
+            .bind(endpoint)
+            .execute(&mut *conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Uses up a subscription's claim code. Returns false if it's wrong or has expired.
+    #[tracing::instrument(skip(self, code_hash), ret, err)]
+    pub async fn redeem_claim(
+        &self,
+        endpoint: &str,
+        code_hash: &str,
+        now: i64,
+    ) -> Result<bool, Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        let result = sqlx::query(
+            r"
+            DELETE FROM subscription_claims
+            WHERE endpoint = ? AND code_hash = ? AND expires_at > ?
+            ",
+        )
+        .bind(endpoint)
+        .bind(code_hash)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[tracing::instrument(skip(self), ret, err)]
+    pub async fn get_matrix_message(
+        &self,
+        endpoint: &str,
+        game: GameId,
+    ) -> Result<Option<String>, Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        let event_id: Option<String> = sqlx::query_scalar(
+            r"
+            SELECT event_id FROM matrix_messages WHERE endpoint = ? AND game_id = ?
+            ",
+        )
+        .bind(endpoint)
+        .bind(game)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        Ok(event_id)
+    }
+
+    #[tracing::instrument(skip(self), err)]
+    pub async fn record_matrix_message(
+        &self,
+        endpoint: &str,
+        game: GameId,
+        event_id: &str,
+    ) -> Result<(), Error> {
         let mut conn = self.pool.acquire().await?;
 
         sqlx::query(
             r"
-            UPDATE subscriptions
-            SET active = 0
-            WHERE endpoint = ?
+            INSERT OR REPLACE INTO matrix_messages (endpoint, game_id, event_id)
+            VALUES (?, ?, ?)
             ",
         )
         .bind(endpoint)
+        .bind(game)
+        .bind(event_id)
         .execute(&mut *conn)
         .await?;
 
         Ok(())
     }
 
+    #[tracing::instrument(skip(self), err)]
+    pub async fn record_delivery_attempt(&self, attempt: &DeliveryAttempt) -> Result<(), Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        insert_delivery_attempt(attempt).execute(&mut *conn).await?;
+
+        Ok(())
+    }
+
+    /// Most recent delivery attempts for a subscription, newest first
+    #[tracing::instrument(skip(self), err)]
+    pub async fn get_delivery_attempts(
+        &self,
+        endpoint: &str,
+        limit: u32,
+    ) -> Result<Vec<DeliveryAttempt>, Error> {
+        let mut conn = self.pool.acquire().await?;
+
+        let attempts = sqlx::query_as(
+            r"
+            SELECT endpoint, game_id, notification, title, body, attempted_at, status_code,
+                   latency_ms, error
+            FROM delivery_log
+            WHERE endpoint = ?
+            ORDER BY attempted_at DESC, id DESC
+            LIMIT ?
+            ",
+        )
+        .bind(endpoint)
+        .bind(limit)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        Ok(attempts)
+    }
+
     #[tracing::instrument(skip(self), err)]
     pub async fn get_stats(&self) -> Result<Stats, Error> {
         let mut conn = self.pool.acquire().await?;
@@ -284,7 +1106,8 @@ impl Store {
         let overall_stats: OverallStats = sqlx::query_as(
             r#"
             SELECT
-                (SELECT COUNT(*) FROM subscriptions) AS total_subscriptions,
+                (SELECT COUNT(*) FROM subscriptions WHERE superseded_by IS NULL)
+                    AS total_subscriptions,
                 (SELECT COUNT(*) FROM subscriptions WHERE active = 1) AS active_subscriptions,
                 (SELECT COUNT(*) FROM alerts) AS notifications_sent
         "#,
@@ -298,6 +1121,7 @@ impl Store {
                 SUBSTR(endpoint, INSTR(endpoint, '//') + 2, INSTR(SUBSTR(endpoint, INSTR(endpoint, '//') + 2), '/') - 1) AS domain,
                 COUNT(*) AS subscriptions_count
             FROM subscriptions
+            WHERE superseded_by IS NULL
             GROUP BY domain
             ORDER BY subscriptions_count DESC
         "#)
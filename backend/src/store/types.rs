@@ -1,7 +1,9 @@
+use chrono::{DateTime, NaiveTime, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
 use squiggle::types::{GameId, Team, TimeStr};
 
-#[derive(Debug, sqlx::FromRow)]
+#[derive(Debug, Clone, sqlx::FromRow)]
 pub struct Game {
     pub id: GameId,
     pub round: u16,
@@ -14,9 +16,49 @@ pub struct Game {
     pub year: u16,
     pub date: String,
     pub tz: String,
+    /// See [`squiggle::rest::types::Game::is_final`]
+    pub is_final: u8,
+    pub is_grand_final: bool,
+}
+
+impl Game {
+    /// Which final the game is, if it's one
+    #[must_use]
+    pub fn final_round(&self) -> Option<Final> {
+        Final::new(self.is_final, self.is_grand_final)
+    }
+}
+
+/// Finals weeks, named in alerts instead of their round number
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Final {
+    Elimination,
+    Qualifying,
+    Semi,
+    Preliminary,
+    Grand,
+    /// Squiggle has it as a final but not which one
+    Other,
 }
 
-#[derive(Debug, Copy, Clone)]
+impl Final {
+    /// From Squiggle's `is_final` and `is_grand_final`
+    #[must_use]
+    pub fn new(is_final: u8, is_grand_final: bool) -> Option<Self> {
+        match (is_final, is_grand_final) {
+            (_, true) | (6, _) => Some(Self::Grand),
+            (0, false) => None,
+            (2, false) => Some(Self::Elimination),
+            (3, false) => Some(Self::Qualifying),
+            (4, false) => Some(Self::Semi),
+            (5, false) => Some(Self::Preliminary),
+            (_, false) => Some(Self::Other),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
 #[repr(u8)]
 pub enum Notification {
     EndOfFirstQuarter,
@@ -27,26 +69,84 @@ pub enum Notification {
 }
 
 impl Notification {
+    /// Every kind of notification
+    pub const ALL: [Notification; 5] = [
+        Notification::EndOfFirstQuarter,
+        Notification::EndOfSecondQuarter,
+        Notification::EndOfThirdQuarter,
+        Notification::EndOfGame,
+        Notification::CloseGame,
+    ];
+
+    /// Stable name for the notification, used in payloads and tags
     #[must_use]
-    pub fn is_quarter_notification(&self) -> bool {
+    pub fn name(&self) -> &'static str {
         match self {
-            Notification::EndOfFirstQuarter
-            | Notification::EndOfSecondQuarter
-            | Notification::EndOfThirdQuarter
-            | Notification::EndOfGame => true,
-            Notification::CloseGame => false,
+            Notification::EndOfFirstQuarter => "end_of_first_quarter",
+            Notification::EndOfSecondQuarter => "end_of_second_quarter",
+            Notification::EndOfThirdQuarter => "end_of_third_quarter",
+            Notification::EndOfGame => "end_of_game",
+            Notification::CloseGame => "close_game",
         }
     }
 
+    /// Bit for the notification in [`Preferences`]
     #[must_use]
+    pub fn bit(self) -> u32 {
+        1 << self as u8
+    }
+}
+
+/// Kinds of alert a subscriber wants, stored as one bit per [`Notification`]. Given to and from
+/// the API as a list of kinds, e.g. `["end_of_game", "close_game"]`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Deserialize, Serialize, sqlx::Type)]
+#[serde(from = "Vec<Notification>", into = "Vec<Notification>")]
+#[sqlx(transparent)]
+pub struct Preferences(u32);
 
-    pub fn is_full_game_notification(&self) -> bool {
-        matches!(self, Notification::EndOfGame)
+impl Preferences {
+    #[must_use]
+    pub fn all() -> Self {
+        Notification::ALL.into_iter().collect()
     }
 
     #[must_use]
-    pub fn is_close_game_notification(&self) -> bool {
-        matches!(self, Notification::CloseGame)
+    pub fn contains(self, notification: Notification) -> bool {
+        self.0 & notification.bit() != 0
+    }
+
+    #[must_use]
+    pub fn with(self, notification: Notification) -> Self {
+        Self(self.0 | notification.bit())
+    }
+
+    #[must_use]
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl FromIterator<Notification> for Preferences {
+    fn from_iter<T: IntoIterator<Item = Notification>>(iter: T) -> Self {
+        iter.into_iter()
+            .fold(Self::default(), |preferences, notification| {
+                preferences.with(notification)
+            })
+    }
+}
+
+impl From<Vec<Notification>> for Preferences {
+    fn from(value: Vec<Notification>) -> Self {
+        value.into_iter().collect()
+    }
+}
+
+impl From<Preferences> for Vec<Notification> {
+    fn from(value: Preferences) -> Self {
+        Notification::ALL
+            .into_iter()
+            .filter(|notification| value.contains(*notification))
+            .collect()
     }
 }
 
@@ -72,6 +172,8 @@ impl TryFrom<squiggle::rest::types::Game> for Game {
             year: value.year,
             date: value.date,
             tz: value.tz,
+            is_final: value.is_final,
+            is_grand_final: value.is_grand_final != 0,
         })
     }
 }
@@ -93,17 +195,344 @@ impl TryFrom<Game> for squiggle::rest::types::Game {
             year: value.year,
             date: value.date,
             tz: value.tz,
+            is_final: value.is_final,
+            is_grand_final: value.is_grand_final.into(),
         })
     }
 }
 
-#[derive(Debug, sqlx::FromRow, Deserialize, Serialize)]
+/// How an alert is delivered to a subscriber
+#[derive(Debug, Copy, Clone, PartialEq, Default, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum Channel {
+    /// Browser web push, `endpoint` is the push service URL
+    #[default]
+    WebPush = 0,
+    /// ntfy, `endpoint` is the topic URL
+    Ntfy = 1,
+    /// Gotify, `endpoint` is the server's message URL
+    Gotify = 2,
+    /// Matrix, `endpoint` is the room's URL on the homeserver
+    Matrix = 3,
+}
+
+/// Language alerts are written in
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum Locale {
+    #[default]
+    En = 0,
+    Es = 1,
+}
+
+/// Games a subscription wants alerts for, on top of any it follows on their own
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum Scope {
+    #[default]
+    All = 0,
+    /// Just finals, for those who only tune in for September
+    Finals = 1,
+    GrandFinal = 2,
+}
+
+/// How much detail alerts go into
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum Style {
+    #[default]
+    Full = 0,
+    /// Just the scores, for watches and lock screens
+    Compact = 1,
+}
+
+#[derive(sqlx::FromRow, Deserialize, Serialize)]
 pub struct Subscription {
-    pub team: Option<Team>,
-    pub close_games: bool,
-    pub final_scores: bool,
-    pub quarter_scores: bool,
+    /// Teams followed, alerts are for every game if there aren't any
+    #[sqlx(json)]
+    pub teams: Vec<Team>,
+    /// Kinds of alert wanted for games with a followed team, or every game if there aren't any
+    pub notifications: Preferences,
+    /// Kinds of alert wanted for games without a followed team
+    pub other_notifications: Preferences,
     pub endpoint: String,
     pub p256dh: String,
     pub auth: String,
+    pub channel: Channel,
+    /// Access token for channels that need one (ntfy, Gotify, Matrix)
+    pub token: Option<String>,
+    /// Web push payload format the client understands, 0 for plain text
+    pub payload_version: u8,
+    /// IANA timezone name, see [`Subscription::timezone`]
+    pub timezone: Option<String>,
+    /// Local time, HH:MM, that alerts stop being sent
+    pub quiet_hours_start: Option<String>,
+    /// Local time, HH:MM, that alerts start being sent again
+    pub quiet_hours_end: Option<String>,
+    /// Seconds after the real event that alerts are sent, for watching on delay
+    pub spoiler_delay: Option<u32>,
+    /// Hold alerts until the subscriber says they're caught up
+    pub hold_alerts: bool,
+    pub locale: Locale,
+    pub style: Style,
+    pub scope: Scope,
+    /// False once unsubscribed, or the endpoint has expired or been superseded
+    pub active: bool,
+    /// See [`crate::api::auth`]
+    #[serde(skip)]
+    pub management_token_hash: Option<String>,
+    /// The endpoint the browser moved this subscription to
+    pub superseded_by: Option<String>,
+    /// See [`Subscription::snooze`]
+    pub snoozed_until: Option<i64>,
+    pub snoozed_year: Option<u16>,
+    pub snoozed_round: Option<u16>,
+}
+
+/// Leaves out the secrets, as subscriptions end up in traces
+impl std::fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let redacted = |secret: bool| if secret { "<redacted>" } else { "<none>" };
+
+        f.debug_struct("Subscription")
+            .field("teams", &self.teams)
+            .field("notifications", &self.notifications)
+            .field("other_notifications", &self.other_notifications)
+            .field("endpoint", &self.endpoint)
+            .field("p256dh", &self.p256dh)
+            .field("auth", &redacted(!self.auth.is_empty()))
+            .field("channel", &self.channel)
+            .field("token", &redacted(self.token.is_some()))
+            .field("payload_version", &self.payload_version)
+            .field("timezone", &self.timezone)
+            .field("quiet_hours_start", &self.quiet_hours_start)
+            .field("quiet_hours_end", &self.quiet_hours_end)
+            .field("spoiler_delay", &self.spoiler_delay)
+            .field("hold_alerts", &self.hold_alerts)
+            .field("locale", &self.locale)
+            .field("style", &self.style)
+            .field("scope", &self.scope)
+            .field("active", &self.active)
+            .field(
+                "management_token_hash",
+                &redacted(self.management_token_hash.is_some()),
+            )
+            .field("superseded_by", &self.superseded_by)
+            .field("snoozed_until", &self.snoozed_until)
+            .field("snoozed_year", &self.snoozed_year)
+            .field("snoozed_round", &self.snoozed_round)
+            .finish()
+    }
+}
+
+/// No alerts are sent while snoozed, it lifts by itself
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Snooze {
+    /// Unix timestamp
+    Until(i64),
+    /// Until the first game of the round
+    Round { year: u16, round: u16 },
+    /// Until the year's first final
+    Finals { year: u16 },
+}
+
+impl Snooze {
+    /// How a snooze is stored, `snoozed_until`, `snoozed_year` and `snoozed_round`
+    #[must_use]
+    pub fn columns(snooze: Option<Self>) -> (Option<i64>, Option<u16>, Option<u16>) {
+        match snooze {
+            None => (None, None, None),
+            Some(Self::Until(until)) => (Some(until), None, None),
+            Some(Self::Round { year, round }) => (None, Some(year), Some(round)),
+            Some(Self::Finals { year }) => (None, Some(year), None),
+        }
+    }
+}
+
+/// A single game a subscription follows, whether or not it has their teams
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow, Deserialize, Serialize)]
+pub struct GameFollow {
+    pub game_id: GameId,
+    /// Kinds of alert wanted for the game
+    pub notifications: Preferences,
+}
+
+/// How quiet hours are stored
+pub const QUIET_HOURS_FORMAT: &str = "%H:%M";
+
+/// Used for subscribers that haven't told us their timezone
+pub const DEFAULT_TIMEZONE: Tz = chrono_tz::Australia::Melbourne;
+
+impl Subscription {
+    /// The subscriber's timezone, Melbourne if they haven't set one
+    #[must_use]
+    pub fn timezone(&self) -> Tz {
+        self.timezone
+            .as_deref()
+            .and_then(|timezone| timezone.parse().ok())
+            .unwrap_or(DEFAULT_TIMEZONE)
+    }
+
+    /// What the subscription is snoozed until, if it is. A snooze until a time that's passed is
+    /// still returned, snoozes are only cleared when the next alert goes out.
+    #[must_use]
+    pub fn snooze(&self) -> Option<Snooze> {
+        match (self.snoozed_until, self.snoozed_year, self.snoozed_round) {
+            (Some(until), _, _) => Some(Snooze::Until(until)),
+            (None, Some(year), Some(round)) => Some(Snooze::Round { year, round }),
+            (None, Some(year), None) => Some(Snooze::Finals { year }),
+            (None, None, _) => None,
+        }
+    }
+
+    pub fn set_snooze(&mut self, snooze: Option<Snooze>) {
+        (self.snoozed_until, self.snoozed_year, self.snoozed_round) = Snooze::columns(snooze);
+    }
+
+    /// Local start and end of the subscriber's quiet hours, if they have any
+    #[must_use]
+    pub fn quiet_hours(&self) -> Option<(NaiveTime, NaiveTime)> {
+        let parse = |time: &Option<String>| {
+            NaiveTime::parse_from_str(time.as_deref()?, QUIET_HOURS_FORMAT).ok()
+        };
+
+        parse(&self.quiet_hours_start).zip(parse(&self.quiet_hours_end))
+    }
+
+    /// If `now` is inside the subscriber's quiet hours, when they end
+    #[must_use]
+    pub fn quiet_until(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let (start, end) = self.quiet_hours()?;
+
+        let timezone = self.timezone();
+        let local = now.with_timezone(&timezone);
+        let time = local.time();
+
+        let quiet = if start <= end {
+            start <= time && time < end
+        } else {
+            // spans midnight
+            time >= start || time < end
+        };
+        if !quiet {
+            return None;
+        }
+
+        let mut date = local.date_naive();
+        if time >= end {
+            date = date.succ_opt()?;
+        }
+
+        // if the end falls in a daylight saving gap, the first time after it will do
+        let until = timezone
+            .from_local_datetime(&date.and_time(end))
+            .earliest()
+            .or_else(|| {
+                timezone
+                    .from_local_datetime(&(date.and_time(end) + TimeDelta::hours(1)))
+                    .earliest()
+            })?;
+
+        Some(until.with_timezone(&Utc))
+    }
+}
+
+/// An alert generated for a game, with the scores at the time
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Alert {
+    pub game_id: GameId,
+    pub notification: Notification,
+    pub home_team: Team,
+    pub away_team: Team,
+    pub home_score: u16,
+    pub away_score: u16,
+    /// JSON encoded [`TimeStr`], like [`Game::timestr`]
+    pub timestr: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum DeliveryStatus {
+    /// Waiting to be sent, or to be retried
+    Pending = 0,
+    Delivered = 1,
+    /// Rejected by the receiving server, retrying won't help
+    Failed = 2,
+    /// Couldn't be delivered before the alert's TTL ran out
+    Expired = 3,
+    /// Waiting for the subscriber to release it
+    Held = 4,
+}
+
+/// Pushes to every subscriber, once a season
+#[derive(Debug, Copy, Clone, PartialEq, sqlx::Type)]
+#[repr(u8)]
+pub enum SeasonEvent {
+    /// After the Grand Final
+    End = 0,
+    /// Just before Opening Round
+    Start = 1,
+}
+
+/// What happened to a claimed delivery
+#[derive(Debug, Clone)]
+pub enum DeliveryOutcome {
+    Completed {
+        status: DeliveryStatus,
+        error: Option<String>,
+    },
+    /// Failed, but worth trying again
+    Retry { next_attempt_at: i64, error: String },
+    /// Not attempted, put back for later
+    Deferred { next_attempt_at: i64 },
+}
+
+/// An outbox entry that's been claimed for sending
+#[derive(Debug, sqlx::FromRow)]
+pub struct Delivery {
+    pub id: i64,
+    /// Including the attempt this delivery was claimed for
+    pub attempts: u32,
+    /// Unix timestamp after which the alert isn't worth delivering
+    pub expires_at: i64,
+    /// Of the alert's game, see [`Game::is_final`]
+    pub is_final: u8,
+    pub is_grand_final: bool,
+    #[sqlx(flatten)]
+    pub alert: Alert,
+    #[sqlx(flatten)]
+    pub subscription: Subscription,
+}
+
+impl Delivery {
+    /// Which final the alert's game is, if it's one
+    #[must_use]
+    pub fn final_round(&self) -> Option<Final> {
+        Final::new(self.is_final, self.is_grand_final)
+    }
+}
+
+/// One attempt at sending an alert to a subscriber
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct DeliveryAttempt {
+    #[serde(skip)]
+    pub endpoint: String,
+    /// `None` for test notifications
+    pub game_id: Option<GameId>,
+    /// `None` for test notifications
+    pub notification: Option<Notification>,
+    pub title: String,
+    pub body: String,
+    /// Unix timestamp
+    pub attempted_at: i64,
+    /// `None` if the request never got a response
+    pub status_code: Option<u16>,
+    pub latency_ms: u32,
+    pub error: Option<String>,
 }
@@ -0,0 +1,783 @@
+use std::collections::HashMap;
+
+use chrono::{Datelike, NaiveDate};
+use serde::Deserialize;
+
+use crate::{
+    channel::Scores,
+    store::types::{Final, Locale, Notification, Style},
+};
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum Error {
+    #[error("Unknown placeholder {{{0}}}")]
+    UnknownPlaceholder(String),
+    #[error("Placeholder {{{0}}} can't be used here")]
+    NotAllowed(String),
+    #[error("Placeholder isn't closed")]
+    Unclosed,
+    #[error("Unmatched }}, use }}}} for a literal brace")]
+    Unmatched,
+    #[error("Invalid {field} template for {locale:?} {style:?}: {source}")]
+    Invalid {
+        locale: Locale,
+        style: Style,
+        field: String,
+        #[source]
+        source: Box<Error>,
+    },
+}
+
+/// A named value that can be used in a template, e.g. `{home_team}`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Placeholder {
+    /// e.g. "Geelong"
+    HomeTeam,
+    AwayTeam,
+    /// e.g. "GEE"
+    HomeAbbreviation,
+    AwayAbbreviation,
+    HomeScore,
+    AwayScore,
+    /// Points between the teams
+    Margin,
+    /// e.g. "Q3", only for quarter time alerts
+    Quarter,
+    /// Game clock, only for close game alerts
+    Clock,
+    /// Number of alerts, only for digest titles
+    Count,
+    /// e.g. "2024", only for season pushes
+    Year,
+    /// Team that won the Grand Final
+    Premiers,
+    /// Day of the first game of the season, e.g. "Friday 7 March"
+    Date,
+    /// Name of the first round of the season, e.g. "Opening Round"
+    Round,
+    /// Games in the first round of the season, e.g. "SYD v HAW · GWS v COL"
+    Fixture,
+    /// e.g. "Friday", only for the date of the first game
+    Weekday,
+    /// Day of the month
+    Day,
+    /// e.g. "March"
+    Month,
+    /// Round number, only for naming rounds
+    Number,
+}
+
+impl Placeholder {
+    fn from_name(name: &str) -> Option<Self> {
+        let placeholder = match name {
+            "home_team" => Placeholder::HomeTeam,
+            "away_team" => Placeholder::AwayTeam,
+            "home_abbr" => Placeholder::HomeAbbreviation,
+            "away_abbr" => Placeholder::AwayAbbreviation,
+            "home_score" => Placeholder::HomeScore,
+            "away_score" => Placeholder::AwayScore,
+            "margin" => Placeholder::Margin,
+            "quarter" => Placeholder::Quarter,
+            "clock" => Placeholder::Clock,
+            "count" => Placeholder::Count,
+            "year" => Placeholder::Year,
+            "premiers" => Placeholder::Premiers,
+            "date" => Placeholder::Date,
+            "round" => Placeholder::Round,
+            "fixture" => Placeholder::Fixture,
+            "weekday" => Placeholder::Weekday,
+            "day" => Placeholder::Day,
+            "month" => Placeholder::Month,
+            "number" => Placeholder::Number,
+            _ => return None,
+        };
+
+        Some(placeholder)
+    }
+
+    fn is_allowed(self, context: Context) -> bool {
+        match self {
+            Placeholder::HomeTeam
+            | Placeholder::AwayTeam
+            | Placeholder::HomeAbbreviation
+            | Placeholder::AwayAbbreviation
+            | Placeholder::HomeScore
+            | Placeholder::AwayScore
+            | Placeholder::Margin => matches!(context, Context::Alert(_) | Context::Summary),
+            Placeholder::Quarter => matches!(
+                context,
+                Context::Alert(
+                    Notification::EndOfFirstQuarter
+                        | Notification::EndOfSecondQuarter
+                        | Notification::EndOfThirdQuarter
+                )
+            ),
+            Placeholder::Clock => matches!(context, Context::Alert(Notification::CloseGame)),
+            Placeholder::Count => matches!(context, Context::DigestTitle),
+            Placeholder::Year => matches!(
+                context,
+                Context::SeasonOver | Context::SeasonDrawn | Context::SeasonStart
+            ),
+            Placeholder::Premiers => matches!(context, Context::SeasonOver),
+            Placeholder::Date | Placeholder::Round | Placeholder::Fixture => {
+                matches!(context, Context::SeasonStart)
+            }
+            Placeholder::Weekday | Placeholder::Day | Placeholder::Month => {
+                matches!(context, Context::SeasonDate)
+            }
+            Placeholder::Number => matches!(context, Context::SeasonRound),
+        }
+    }
+}
+
+/// Where a template is used, which decides the placeholders it can have
+#[derive(Debug, Clone, Copy)]
+pub enum Context {
+    Alert(Notification),
+    Summary,
+    DigestTitle,
+    SeasonOver,
+    /// The end of a season with a drawn Grand Final, so without premiers
+    SeasonDrawn,
+    SeasonStart,
+    SeasonDate,
+    SeasonRound,
+}
+
+/// What placeholders are replaced with, placeholders without a value are left empty
+#[derive(Debug, Default)]
+pub struct Values {
+    pub scores: Option<Scores>,
+    pub quarter: Option<String>,
+    pub clock: Option<String>,
+    pub count: Option<usize>,
+    pub year: Option<u16>,
+    pub premiers: Option<String>,
+    pub date: Option<String>,
+    pub round: Option<String>,
+    pub fixture: Option<String>,
+    pub weekday: Option<String>,
+    pub day: Option<u32>,
+    pub month: Option<String>,
+    pub number: Option<u16>,
+}
+
+impl Values {
+    fn get(&self, placeholder: Placeholder) -> Option<String> {
+        let scores = self.scores.as_ref();
+        match placeholder {
+            Placeholder::HomeTeam => scores.map(|scores| scores.home_team.to_string()),
+            Placeholder::AwayTeam => scores.map(|scores| scores.away_team.to_string()),
+            Placeholder::HomeAbbreviation => {
+                scores.map(|scores| scores.home_team.abbreviation().to_string())
+            }
+            Placeholder::AwayAbbreviation => {
+                scores.map(|scores| scores.away_team.abbreviation().to_string())
+            }
+            Placeholder::HomeScore => scores.map(|scores| scores.home_score.to_string()),
+            Placeholder::AwayScore => scores.map(|scores| scores.away_score.to_string()),
+            Placeholder::Margin => {
+                scores.map(|scores| scores.home_score.abs_diff(scores.away_score).to_string())
+            }
+            Placeholder::Quarter => self.quarter.clone(),
+            Placeholder::Clock => self.clock.clone(),
+            Placeholder::Count => self.count.map(|count| count.to_string()),
+            Placeholder::Year => self.year.map(|year| year.to_string()),
+            Placeholder::Premiers => self.premiers.clone(),
+            Placeholder::Date => self.date.clone(),
+            Placeholder::Round => self.round.clone(),
+            Placeholder::Fixture => self.fixture.clone(),
+            Placeholder::Weekday => self.weekday.clone(),
+            Placeholder::Day => self.day.map(|day| day.to_string()),
+            Placeholder::Month => self.month.clone(),
+            Placeholder::Number => self.number.map(|number| number.to_string()),
+        }
+    }
+}
+
+enum Part<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// Text with named placeholders, e.g. `"End of {quarter}: {home_team} {home_score}"`. `{{` and
+/// `}}` are literal braces.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct Template(String);
+
+impl Template {
+    #[must_use]
+    pub fn new(template: &str) -> Self {
+        Self(template.to_string())
+    }
+
+    fn parts(&self) -> Result<Vec<Part<'_>>, Error> {
+        let mut parts = Vec::new();
+        let mut rest = self.0.as_str();
+        while let Some(index) = rest.find(['{', '}']) {
+            let (text, tail) = rest.split_at(index);
+            if !text.is_empty() {
+                parts.push(Part::Text(text));
+            }
+
+            if let Some(tail) = tail.strip_prefix("{{") {
+                parts.push(Part::Text("{"));
+                rest = tail;
+            } else if let Some(tail) = tail.strip_prefix("}}") {
+                parts.push(Part::Text("}"));
+                rest = tail;
+            } else if let Some(tail) = tail.strip_prefix('{') {
+                let (name, tail) = tail.split_once('}').ok_or(Error::Unclosed)?;
+                parts.push(Part::Placeholder(name));
+                rest = tail;
+            } else {
+                return Err(Error::Unmatched);
+            }
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest));
+        }
+
+        Ok(parts)
+    }
+
+    /// Checks the template parses and only uses placeholders that have a value in `context`
+    pub fn validate(&self, context: Context) -> Result<(), Error> {
+        for part in self.parts()? {
+            let Part::Placeholder(name) = part else {
+                continue;
+            };
+            let placeholder = Placeholder::from_name(name)
+                .ok_or_else(|| Error::UnknownPlaceholder(name.to_string()))?;
+            if !placeholder.is_allowed(context) {
+                return Err(Error::NotAllowed(name.to_string()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fills in the placeholders, templates are validated up front so anything that doesn't
+    /// parse is sent as is
+    #[must_use]
+    pub fn render(&self, values: &Values) -> String {
+        let Ok(parts) = self.parts() else {
+            return self.0.clone();
+        };
+
+        let mut rendered = String::with_capacity(self.0.len());
+        for part in parts {
+            match part {
+                Part::Text(text) => rendered.push_str(text),
+                Part::Placeholder(name) => {
+                    if let Some(value) = Placeholder::from_name(name).and_then(|p| values.get(p)) {
+                        rendered.push_str(&value);
+                    }
+                }
+            }
+        }
+
+        rendered
+    }
+}
+
+/// Text of an alert of a single kind
+#[derive(Debug, Clone, Deserialize)]
+pub struct Message {
+    pub title: Template,
+    pub body: Template,
+    /// Heads the alert in a digest, e.g. "3/4 Time"
+    pub label: Template,
+}
+
+impl Message {
+    fn new(title: &str, body: &str, label: &str) -> Self {
+        Self {
+            title: Template::new(title),
+            body: Template::new(body),
+            label: Template::new(label),
+        }
+    }
+}
+
+/// What each final is called, alerts for finals have it before their title, e.g.
+/// "Preliminary Final: Full time"
+#[derive(Debug, Clone, Deserialize)]
+pub struct Finals {
+    pub elimination: String,
+    pub qualifying: String,
+    pub semi: String,
+    pub preliminary: String,
+    pub grand: String,
+    /// For a final Squiggle doesn't say the kind of
+    pub other: String,
+}
+
+impl Finals {
+    #[must_use]
+    pub fn name(&self, final_round: Final) -> &str {
+        match final_round {
+            Final::Elimination => &self.elimination,
+            Final::Qualifying => &self.qualifying,
+            Final::Semi => &self.semi,
+            Final::Preliminary => &self.preliminary,
+            Final::Grand => &self.grand,
+            Final::Other => &self.other,
+        }
+    }
+
+    fn new(names: [&str; 6]) -> Self {
+        let [elimination, qualifying, semi, preliminary, grand, other] = names.map(String::from);
+        Self {
+            elimination,
+            qualifying,
+            semi,
+            preliminary,
+            grand,
+            other,
+        }
+    }
+}
+
+/// Text of the pushes to every subscriber at either end of the season
+#[derive(Debug, Clone, Deserialize)]
+pub struct SeasonText {
+    /// After the Grand Final, e.g. "Congratulations {premiers}, {year} premiers!"
+    pub over: Template,
+    /// After a drawn Grand Final, which has no premiers
+    pub over_drawn: Template,
+    /// Just before the first game, e.g. "The {year} season starts {date}. {round}: {fixture}"
+    pub start: Template,
+    /// Day of the first game, e.g. "{weekday} {day} {month}"
+    pub date: Template,
+    /// Monday first
+    pub weekdays: [String; 7],
+    pub months: [String; 12],
+    /// Round 0
+    pub opening_round: String,
+    /// Every other round, e.g. "Round {number}"
+    pub round: Template,
+}
+
+impl SeasonText {
+    /// e.g. "Friday 7 March"
+    #[must_use]
+    pub fn date(&self, date: NaiveDate) -> String {
+        self.date.render(&Values {
+            weekday: Some(self.weekdays[date.weekday().num_days_from_monday() as usize].clone()),
+            day: Some(date.day()),
+            month: Some(self.months[date.month0() as usize].clone()),
+            ..Values::default()
+        })
+    }
+
+    /// e.g. "Opening Round", or "Round 1"
+    #[must_use]
+    pub fn round(&self, round: u16) -> String {
+        if round == 0 {
+            return self.opening_round.clone();
+        }
+
+        self.round.render(&Values {
+            number: Some(round),
+            ..Values::default()
+        })
+    }
+
+    fn english() -> Self {
+        Self {
+            over: Template::new(
+                "Congratulations {premiers}, {year} premiers! That's the season, see you next \
+                 year.",
+            ),
+            over_drawn: Template::new("That's the {year} season, see you next year."),
+            start: Template::new("The {year} season starts {date}. {round}: {fixture}"),
+            date: Template::new("{weekday} {day} {month}"),
+            weekdays: [
+                "Monday",
+                "Tuesday",
+                "Wednesday",
+                "Thursday",
+                "Friday",
+                "Saturday",
+                "Sunday",
+            ]
+            .map(String::from),
+            months: [
+                "January",
+                "February",
+                "March",
+                "April",
+                "May",
+                "June",
+                "July",
+                "August",
+                "September",
+                "October",
+                "November",
+                "December",
+            ]
+            .map(String::from),
+            opening_round: String::from("Opening Round"),
+            round: Template::new("Round {number}"),
+        }
+    }
+
+    fn spanish() -> Self {
+        Self {
+            over: Template::new(
+                "¡Felicitaciones {premiers}, campeones de {year}! Se terminó la temporada, nos \
+                 vemos el año que viene.",
+            ),
+            over_drawn: Template::new(
+                "Se terminó la temporada {year}, nos vemos el año que viene.",
+            ),
+            start: Template::new("La temporada {year} empieza el {date}. {round}: {fixture}"),
+            date: Template::new("{weekday} {day} de {month}"),
+            weekdays: [
+                "lunes",
+                "martes",
+                "miércoles",
+                "jueves",
+                "viernes",
+                "sábado",
+                "domingo",
+            ]
+            .map(String::from),
+            months: [
+                "enero",
+                "febrero",
+                "marzo",
+                "abril",
+                "mayo",
+                "junio",
+                "julio",
+                "agosto",
+                "septiembre",
+                "octubre",
+                "noviembre",
+                "diciembre",
+            ]
+            .map(String::from),
+            opening_round: String::from("Ronda inaugural"),
+            round: Template::new("Ronda {number}"),
+        }
+    }
+
+    fn validate(&self) -> Result<(), (String, Error)> {
+        for (field, template, context) in [
+            ("over", &self.over, Context::SeasonOver),
+            ("over_drawn", &self.over_drawn, Context::SeasonDrawn),
+            ("start", &self.start, Context::SeasonStart),
+            ("date", &self.date, Context::SeasonDate),
+            ("round", &self.round, Context::SeasonRound),
+        ] {
+            template
+                .validate(context)
+                .map_err(|err| (format!("season.{field}"), err))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Alert text for one locale and style
+#[derive(Debug, Clone)]
+pub struct Templates {
+    pub end_of_first_quarter: Message,
+    pub end_of_second_quarter: Message,
+    pub end_of_third_quarter: Message,
+    pub end_of_game: Message,
+    pub close_game: Message,
+    /// Scores of a game in a digest, e.g. "GEE 64-52 HAW"
+    pub summary: Template,
+    /// Title of a digest, `{count}` is how many alerts are in it
+    pub digest_title: Template,
+    pub finals: Finals,
+    pub season: SeasonText,
+}
+
+impl Templates {
+    #[must_use]
+    pub fn get(&self, kind: Notification) -> &Message {
+        match kind {
+            Notification::EndOfFirstQuarter => &self.end_of_first_quarter,
+            Notification::EndOfSecondQuarter => &self.end_of_second_quarter,
+            Notification::EndOfThirdQuarter => &self.end_of_third_quarter,
+            Notification::EndOfGame => &self.end_of_game,
+            Notification::CloseGame => &self.close_game,
+        }
+    }
+
+    fn english() -> Self {
+        let score = "{home_team} {home_score} - {away_team} {away_score}";
+        Self {
+            end_of_first_quarter: Message::new(
+                "End of {quarter}",
+                &format!("End of {{quarter}}: {score}"),
+                "1/4 Time",
+            ),
+            end_of_second_quarter: Message::new(
+                "End of {quarter}",
+                &format!("End of {{quarter}}: {score}"),
+                "1/2 Time",
+            ),
+            end_of_third_quarter: Message::new(
+                "End of {quarter}",
+                &format!("End of {{quarter}}: {score}"),
+                "3/4 Time",
+            ),
+            end_of_game: Message::new("Full time", &format!("End of game: {score}"), "Full Time"),
+            close_game: Message::new(
+                "Close game",
+                &format!("Close game ({{clock}}): {score}"),
+                "Close game ({clock})",
+            ),
+            summary: Template::new("{home_abbr} {home_score}-{away_score} {away_abbr}"),
+            digest_title: Template::new("{count} score updates"),
+            finals: Finals::new([
+                "Elimination Final",
+                "Qualifying Final",
+                "Semi Final",
+                "Preliminary Final",
+                "Grand Final",
+                "Final",
+            ]),
+            season: SeasonText::english(),
+        }
+    }
+
+    fn spanish() -> Self {
+        let score = "{home_team} {home_score} - {away_team} {away_score}";
+        Self {
+            end_of_first_quarter: Message::new(
+                "Fin del {quarter}",
+                &format!("Fin del {{quarter}}: {score}"),
+                "Primer cuarto",
+            ),
+            end_of_second_quarter: Message::new(
+                "Fin del {quarter}",
+                &format!("Fin del {{quarter}}: {score}"),
+                "Medio tiempo",
+            ),
+            end_of_third_quarter: Message::new(
+                "Fin del {quarter}",
+                &format!("Fin del {{quarter}}: {score}"),
+                "Tercer cuarto",
+            ),
+            end_of_game: Message::new(
+                "Final del partido",
+                &format!("Final del partido: {score}"),
+                "Final",
+            ),
+            close_game: Message::new(
+                "Partido reñido",
+                &format!("Partido reñido ({{clock}}): {score}"),
+                "Partido reñido ({clock})",
+            ),
+            summary: Template::new("{home_abbr} {home_score}-{away_score} {away_abbr}"),
+            digest_title: Template::new("{count} actualizaciones de marcador"),
+            finals: Finals::new([
+                "Final de eliminación",
+                "Final de clasificación",
+                "Semifinal",
+                "Final preliminar",
+                "Gran Final",
+                "Final",
+            ]),
+            season: SeasonText::spanish(),
+        }
+    }
+
+    /// Short enough to read on a watch, the label is the title and the body is just the scores.
+    /// Season pushes are given as `[over, over_drawn, start]`, without the fixture.
+    fn compact(self, digest_title: &str, season: [&str; 3]) -> Self {
+        let compact = |message: Message| Message {
+            title: message.label.clone(),
+            body: self.summary.clone(),
+            label: message.label,
+        };
+
+        Self {
+            end_of_first_quarter: compact(self.end_of_first_quarter.clone()),
+            end_of_second_quarter: compact(self.end_of_second_quarter.clone()),
+            end_of_third_quarter: compact(self.end_of_third_quarter.clone()),
+            end_of_game: compact(self.end_of_game.clone()),
+            close_game: compact(self.close_game.clone()),
+            summary: self.summary.clone(),
+            digest_title: Template::new(digest_title),
+            finals: self.finals,
+            season: SeasonText {
+                over: Template::new(season[0]),
+                over_drawn: Template::new(season[1]),
+                start: Template::new(season[2]),
+                ..self.season
+            },
+        }
+    }
+
+    fn validate(&self) -> Result<(), (String, Error)> {
+        for kind in Notification::ALL {
+            let message = self.get(kind);
+            for (field, template) in [
+                ("title", &message.title),
+                ("body", &message.body),
+                ("label", &message.label),
+            ] {
+                template
+                    .validate(Context::Alert(kind))
+                    .map_err(|err| (format!("{}.{field}", kind.name()), err))?;
+            }
+        }
+        self.summary
+            .validate(Context::Summary)
+            .map_err(|err| (String::from("summary"), err))?;
+        self.digest_title
+            .validate(Context::DigestTitle)
+            .map_err(|err| (String::from("digest_title"), err))?;
+        self.season.validate()?;
+
+        Ok(())
+    }
+
+    fn apply(&mut self, overrides: Overrides) {
+        let Overrides {
+            end_of_first_quarter,
+            end_of_second_quarter,
+            end_of_third_quarter,
+            end_of_game,
+            close_game,
+            summary,
+            digest_title,
+            finals,
+            season,
+        } = overrides;
+
+        for (message, replacement) in [
+            (&mut self.end_of_first_quarter, end_of_first_quarter),
+            (&mut self.end_of_second_quarter, end_of_second_quarter),
+            (&mut self.end_of_third_quarter, end_of_third_quarter),
+            (&mut self.end_of_game, end_of_game),
+            (&mut self.close_game, close_game),
+        ] {
+            if let Some(replacement) = replacement {
+                *message = replacement;
+            }
+        }
+        if let Some(summary) = summary {
+            self.summary = summary;
+        }
+        if let Some(digest_title) = digest_title {
+            self.digest_title = digest_title;
+        }
+        if let Some(finals) = finals {
+            self.finals = finals;
+        }
+        if let Some(season) = season {
+            self.season = season;
+        }
+    }
+}
+
+/// Replacement templates for a locale and style, anything that isn't given keeps its default
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Overrides {
+    pub end_of_first_quarter: Option<Message>,
+    pub end_of_second_quarter: Option<Message>,
+    pub end_of_third_quarter: Option<Message>,
+    pub end_of_game: Option<Message>,
+    pub close_game: Option<Message>,
+    pub summary: Option<Template>,
+    pub digest_title: Option<Template>,
+    pub finals: Option<Finals>,
+    pub season: Option<SeasonText>,
+}
+
+/// Overrides for each locale and style. Configured as JSON, e.g.
+/// `{"en": {"compact": {"close_game": {"title": "Close!", "body": "{margin} pts ({clock})",
+/// "label": "Close"}}}}`
+pub type Config = HashMap<Locale, HashMap<Style, Overrides>>;
+
+/// Alert text for every locale and style subscribers can pick
+#[derive(Debug, Clone)]
+pub struct Catalog {
+    templates: HashMap<(Locale, Style), Templates>,
+}
+
+impl Default for Catalog {
+    fn default() -> Self {
+        let english = Templates::english();
+        let spanish = Templates::spanish();
+        let templates = HashMap::from([
+            (
+                (Locale::En, Style::Compact),
+                english.clone().compact(
+                    "{count} updates",
+                    [
+                        "{premiers}, {year} premiers",
+                        "That's the {year} season",
+                        "The {year} season starts {date}",
+                    ],
+                ),
+            ),
+            ((Locale::En, Style::Full), english),
+            (
+                (Locale::Es, Style::Compact),
+                spanish.clone().compact(
+                    "{count} marcadores",
+                    [
+                        "{premiers}, campeones de {year}",
+                        "Fin de la temporada {year}",
+                        "La temporada {year} empieza el {date}",
+                    ],
+                ),
+            ),
+            ((Locale::Es, Style::Full), spanish),
+        ]);
+
+        Self { templates }
+    }
+}
+
+impl Catalog {
+    /// Replaces the default templates with the configured ones, and checks they're all valid
+    pub fn with_config(mut self, config: Config) -> Result<Self, Error> {
+        for (locale, styles) in config {
+            for (style, overrides) in styles {
+                if let Some(templates) = self.templates.get_mut(&(locale, style)) {
+                    templates.apply(overrides);
+                }
+            }
+        }
+        self.validate()?;
+
+        Ok(self)
+    }
+
+    /// Checks every template only uses placeholders that it will have values for
+    pub fn validate(&self) -> Result<(), Error> {
+        for (&(locale, style), templates) in &self.templates {
+            templates
+                .validate()
+                .map_err(|(field, source)| Error::Invalid {
+                    locale,
+                    style,
+                    field,
+                    source: Box::new(source),
+                })?;
+        }
+
+        Ok(())
+    }
+
+    /// Templates for a locale and style, every combination has defaults
+    #[must_use]
+    pub fn get(&self, locale: Locale, style: Style) -> &Templates {
+        self.templates
+            .get(&(locale, style))
+            .or_else(|| self.templates.get(&(Locale::default(), Style::default())))
+            .expect("default templates should exist")
+    }
+}
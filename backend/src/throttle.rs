@@ -0,0 +1,75 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// The push service an endpoint belongs to, e.g. `fcm.googleapis.com`. Matches the domains
+/// reported by [`crate::store::Store::get_stats`].
+#[must_use]
+pub fn domain(endpoint: &str) -> &str {
+    let rest = endpoint.split_once("//").map_or(endpoint, |(_, rest)| rest);
+
+    rest.split_once('/').map_or(rest, |(domain, _)| domain)
+}
+
+/// Limits how many requests are in flight to each push service, and tracks push services that
+/// have asked us to back off, so the rest of a fan-out waits rather than hammering a service
+/// that's already rate limiting us
+#[derive(Clone)]
+pub struct Throttle {
+    per_domain: usize,
+    permits: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+    blocked_until: Arc<Mutex<HashMap<String, i64>>>,
+}
+
+impl Throttle {
+    /// Allows up to `per_domain` requests in flight to each push service
+    #[must_use]
+    pub fn new(per_domain: usize) -> Self {
+        Self {
+            per_domain,
+            permits: Arc::default(),
+            blocked_until: Arc::default(),
+        }
+    }
+
+    /// Waits for a free slot to send to the endpoint's push service, held until the permit is
+    /// dropped
+    pub async fn acquire(&self, endpoint: &str) -> OwnedSemaphorePermit {
+        let semaphore = self
+            .permits
+            .lock()
+            .expect("throttle lock poisoned")
+            .entry(domain(endpoint).to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.per_domain)))
+            .clone();
+
+        semaphore
+            .acquire_owned()
+            .await
+            .expect("throttle semaphores are never closed")
+    }
+
+    /// Unix timestamp until which the endpoint's push service shouldn't be sent to, if any
+    #[must_use]
+    pub fn blocked_until(&self, endpoint: &str, now: i64) -> Option<i64> {
+        let blocked_until = self.blocked_until.lock().expect("throttle lock poisoned");
+
+        blocked_until
+            .get(domain(endpoint))
+            .copied()
+            .filter(|until| *until > now)
+    }
+
+    /// Holds off sending to the endpoint's push service until `until`
+    pub fn block(&self, endpoint: &str, until: i64) {
+        let mut blocked_until = self.blocked_until.lock().expect("throttle lock poisoned");
+
+        let entry = blocked_until
+            .entry(domain(endpoint).to_string())
+            .or_default();
+        *entry = (*entry).max(until);
+    }
+}
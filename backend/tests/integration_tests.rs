@@ -1,15 +1,39 @@
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
 use footy_alerts::{
-    notifier::Notifier,
+    api::routes::create_router,
+    channel::matrix,
+    mqtt::Publisher,
+    notifier::{
+        digest, game_start, FanOut, Notification, Notifier, Payload, Quarter, PAYLOAD_VERSION,
+    },
+    policy::{Collapse, Policies, Policy, Urgency},
     processor::Processor,
-    store::{types::Subscription, Store},
+    season::Season,
+    store::{
+        types::{
+            Alert as AlertRecord, Channel, DeliveryAttempt, Final, Game, GameFollow, Locale,
+            Notification as DbNotification, Preferences, Scope, Snooze, Style, Subscription,
+        },
+        Store,
+    },
+    templates::{self, Catalog, Template},
 };
 use httptest::{matchers::*, responders::*, Expectation, Server};
+use rumqttc::{AsyncClient, MqttOptions, Packet, QoS};
+use serde_json::{json, Value};
 use sqlx::SqlitePool;
 use squiggle::{
-    event::types::{CompleteEvent, Event, TimeStrEvent},
+    event::types::{CompleteEvent, Event, Score, ScoreEvent, TimeStrEvent},
     rest::Client,
-    types::{Team, TimeStr},
+    types::{GameId, Team, TimeStr},
 };
+use tower::ServiceExt;
 
 const TEST_PRIVATE_KEY: &str = "EHXHoeBHyP8hqP5pHfvTRSNXUATFGQUlwBgejQT80qM";
 const TEST_AUTH: &str = "ENDd0ot5n0ftnJlA658u9Q";
@@ -47,58 +71,143 @@ fn expect_notification(mock_server: &Server, path: &str) {
 }
 
 struct TestSubscriptionBuilder {
-    team: Option<Team>,
-    close_games: bool,
-    final_scores: bool,
-    quarter_scores: bool,
+    teams: Vec<Team>,
+    notifications: Preferences,
+    other_notifications: Preferences,
     endpoint: String,
     p256dh: Option<String>,
     auth: Option<String>,
+    channel: Channel,
+    token: Option<String>,
+    payload_version: u8,
+    timezone: Option<String>,
+    quiet_hours: Option<(String, String)>,
+    spoiler_delay: Option<u32>,
+    hold_alerts: bool,
+    locale: Locale,
+    style: Style,
+    scope: Scope,
 }
 
 impl TestSubscriptionBuilder {
     #[must_use]
     fn new(endpoint: String) -> Self {
         Self {
-            team: None,
-            close_games: false,
-            final_scores: false,
-            quarter_scores: false,
+            teams: Vec::new(),
+            notifications: Preferences::default(),
+            other_notifications: Preferences::default(),
             endpoint,
             p256dh: None,
             auth: None,
+            channel: Channel::WebPush,
+            token: None,
+            payload_version: 0,
+            timezone: None,
+            quiet_hours: None,
+            spoiler_delay: None,
+            hold_alerts: false,
+            locale: Locale::En,
+            style: Style::Full,
+            scope: Scope::All,
         }
     }
     #[must_use]
     fn team(mut self, team: Team) -> Self {
-        self.team = Some(team);
+        self.teams.push(team);
+        self
+    }
+    #[must_use]
+    fn notification(mut self, notification: DbNotification) -> Self {
+        self.notifications = self.notifications.with(notification);
+        self
+    }
+    #[must_use]
+    fn other_notification(mut self, notification: DbNotification) -> Self {
+        self.other_notifications = self.other_notifications.with(notification);
+        self
+    }
+    #[must_use]
+    fn close_games(self) -> Self {
+        self.notification(DbNotification::CloseGame)
+    }
+    #[must_use]
+    fn final_scores(self) -> Self {
+        self.notification(DbNotification::EndOfGame)
+    }
+    #[must_use]
+    fn quarter_scores(self) -> Self {
+        self.notification(DbNotification::EndOfFirstQuarter)
+            .notification(DbNotification::EndOfSecondQuarter)
+            .notification(DbNotification::EndOfThirdQuarter)
+    }
+    #[must_use]
+    fn channel(mut self, channel: Channel, token: Option<&str>) -> Self {
+        self.channel = channel;
+        self.token = token.map(String::from);
+        self
+    }
+    #[must_use]
+    fn json_payload(mut self) -> Self {
+        self.payload_version = PAYLOAD_VERSION;
+        self
+    }
+    #[must_use]
+    fn timezone(mut self, timezone: &str) -> Self {
+        self.timezone = Some(timezone.to_string());
+        self
+    }
+    #[must_use]
+    fn quiet_hours(mut self, start: &str, end: &str) -> Self {
+        self.quiet_hours = Some((start.to_string(), end.to_string()));
+        self
+    }
+    #[must_use]
+    fn spoiler_delay(mut self, seconds: u32) -> Self {
+        self.spoiler_delay = Some(seconds);
         self
     }
     #[must_use]
-    fn close_games(mut self) -> Self {
-        self.close_games = true;
+    fn hold_alerts(mut self) -> Self {
+        self.hold_alerts = true;
         self
     }
     #[must_use]
-    fn final_scores(mut self) -> Self {
-        self.final_scores = true;
+    fn locale(mut self, locale: Locale, style: Style) -> Self {
+        self.locale = locale;
+        self.style = style;
         self
     }
     #[must_use]
-    fn quarter_scores(mut self) -> Self {
-        self.quarter_scores = true;
+    fn scope(mut self, scope: Scope) -> Self {
+        self.scope = scope;
         self
     }
     #[must_use]
     fn build(self) -> Subscription {
         Subscription {
-            team: self.team,
-            close_games: self.close_games,
-            final_scores: self.final_scores,
-            quarter_scores: self.quarter_scores,
+            teams: self.teams,
+            notifications: self.notifications,
+            other_notifications: self.other_notifications,
             endpoint: self.endpoint,
             p256dh: self.p256dh.unwrap_or_else(|| TEST_P256DH.to_string()),
             auth: self.auth.unwrap_or_else(|| TEST_AUTH.to_string()),
+            channel: self.channel,
+            token: self.token,
+            payload_version: self.payload_version,
+            timezone: self.timezone,
+            quiet_hours_start: self.quiet_hours.as_ref().map(|(start, _)| start.clone()),
+            quiet_hours_end: self.quiet_hours.map(|(_, end)| end),
+            spoiler_delay: self.spoiler_delay,
+            hold_alerts: self.hold_alerts,
+            locale: self.locale,
+            style: self.style,
+            scope: self.scope,
+            active: true,
+            management_token_hash: None,
+            superseded_by: None,
+            snoozed_until: None,
+            snoozed_year: None,
+            snoozed_round: None,
         }
     }
 }
@@ -247,6 +356,703 @@ async fn it_filters_notifications_by_team(pool: SqlitePool) -> sqlx::Result<()>
     Ok(())
 }
 
+/// Records an alert and returns the endpoints a delivery was queued for, in the order they
+/// were queued
+async fn alert_recipients(
+    store: &Store,
+    pool: &SqlitePool,
+    game_id: GameId,
+    home_team: Team,
+    away_team: Team,
+    notification: DbNotification,
+) -> Vec<String> {
+    let alert = AlertRecord {
+        game_id,
+        notification,
+        home_team,
+        away_team,
+        home_score: 0,
+        away_score: 0,
+        timestr: None,
+    };
+    let now = chrono::Utc::now().timestamp();
+    store
+        .record_alert(&alert, now, now, now + 60)
+        .await
+        .expect("Couldn't record alert")
+        .expect("Alert was already recorded");
+
+    sqlx::query_scalar(
+        r"
+        SELECT endpoint FROM outbox JOIN alerts USING (alert_id)
+        WHERE alerts.id = ? AND alerts.notification = ?
+        ORDER BY outbox.rowid
+        ",
+    )
+    .bind(game_id)
+    .bind(notification as u8)
+    .fetch_all(pool)
+    .await
+    .expect("Couldn't get outbox")
+}
+
+#[sqlx::test]
+async fn it_matches_any_followed_team(pool: SqlitePool) -> sqlx::Result<()> {
+    let store = Store::new_from_pool(pool.clone());
+
+    for (endpoint, teams) in [
+        ("/both/", vec![Team::Geelong, Team::StKilda]),
+        ("/neither/", vec![Team::Geelong, Team::Hawthorn]),
+        ("/all/", vec![]),
+    ] {
+        let mut subscription = TestSubscriptionBuilder::new(endpoint.to_string()).final_scores();
+        for team in teams {
+            subscription = subscription.team(team);
+        }
+        store
+            .add_subscription(subscription.build())
+            .await
+            .expect("Couldn't add subscription");
+    }
+
+    let endpoints = alert_recipients(
+        &store,
+        &pool,
+        35740,
+        Team::GreaterWesternSydney,
+        Team::StKilda,
+        DbNotification::EndOfGame,
+    )
+    .await;
+    assert_eq!(endpoints, vec!["/both/", "/all/"]);
+
+    // resubscribing replaces the teams rather than adding to them
+    store
+        .add_subscription(
+            TestSubscriptionBuilder::new(String::from("/both/"))
+                .team(Team::Hawthorn)
+                .final_scores()
+                .build(),
+        )
+        .await
+        .expect("Couldn't add subscription");
+    let subscription = store
+        .get_subscription_for_endpoint("/both/")
+        .await
+        .expect("Couldn't get subscription")
+        .expect("Subscription should exist");
+    assert_eq!(subscription.teams, vec![Team::Hawthorn]);
+
+    Ok(())
+}
+
+/// Runs the migrations, with `seed` run just before the migration `version` so there's data in
+/// the old schema for it to migrate. All on one connection, so no others are left with a stale
+/// schema.
+async fn migrate_with_seed(pool: &SqlitePool, version: i64, seed: &str) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    for migration in sqlx::migrate!().iter() {
+        if migration.version == version {
+            sqlx::query(seed).execute(&mut *conn).await?;
+        }
+        sqlx::query(&migration.sql).execute(&mut *conn).await?;
+    }
+
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn it_moves_existing_teams_into_subscription_teams(pool: SqlitePool) -> sqlx::Result<()> {
+    migrate_with_seed(
+        &pool,
+        20240921090512,
+        r"
+        INSERT INTO subscriptions (team, close_games, final_scores, quarter_scores,
+                                   endpoint, p256dh, auth)
+        VALUES (7, 1, 1, 1, '/geelong/', '', ''), (NULL, 1, 1, 1, '/all/', '', '')
+        ",
+    )
+    .await?;
+
+    let store = Store::new_from_pool(pool);
+    for (endpoint, teams) in [("/geelong/", vec![Team::Geelong]), ("/all/", vec![])] {
+        let subscription = store
+            .get_subscription_for_endpoint(endpoint)
+            .await
+            .expect("Couldn't get subscription")
+            .expect("Subscription should exist");
+        assert_eq!(subscription.teams, teams);
+    }
+
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn it_migrates_preference_flags_to_notification_kinds(pool: SqlitePool) -> sqlx::Result<()> {
+    migrate_with_seed(
+        &pool,
+        20240928081455,
+        r"
+        INSERT INTO subscriptions (close_games, final_scores, quarter_scores, endpoint, p256dh,
+                                   auth)
+        VALUES (0, 0, 1, '/quarters/', '', ''), (1, 1, 0, '/close-and-final/', '', ''),
+               (1, 1, 1, '/everything/', '', ''), (0, 0, 0, '/nothing/', '', '')
+        ",
+    )
+    .await?;
+
+    let store = Store::new_from_pool(pool);
+    for (endpoint, notifications) in [
+        (
+            "/quarters/",
+            vec![
+                DbNotification::EndOfFirstQuarter,
+                DbNotification::EndOfSecondQuarter,
+                DbNotification::EndOfThirdQuarter,
+            ],
+        ),
+        (
+            "/close-and-final/",
+            vec![DbNotification::EndOfGame, DbNotification::CloseGame],
+        ),
+        ("/everything/", DbNotification::ALL.to_vec()),
+        ("/nothing/", vec![]),
+    ] {
+        let subscription = store
+            .get_subscription_for_endpoint(endpoint)
+            .await
+            .expect("Couldn't get subscription")
+            .expect("Subscription should exist");
+        assert_eq!(
+            Vec::from(subscription.notifications),
+            notifications,
+            "{endpoint}"
+        );
+    }
+
+    Ok(())
+}
+
+#[sqlx::test(migrations = false)]
+async fn it_keeps_outbox_rows_on_their_alerts_through_a_vacuum(
+    pool: SqlitePool,
+) -> sqlx::Result<()> {
+    // the deleted alert leaves a gap in the rowids, which VACUUM is allowed to close up
+    migrate_with_seed(
+        &pool,
+        20241123084105,
+        r"
+        INSERT INTO alerts (id, notification, home_team, away_team, home_score, away_score,
+                            created_at)
+        VALUES (35740, 0, 9, 15, 20, 18, 0), (35740, 3, 9, 15, 80, 79, 0),
+               (35750, 3, 7, 10, 94, 82, 0);
+        DELETE FROM alerts WHERE notification = 0;
+        INSERT INTO outbox (alert_id, endpoint, next_attempt_at, expires_at, created_at)
+        VALUES (2, '/gws/', 0, 3600, 0), (3, '/geelong/', 0, 3600, 0);
+        ",
+    )
+    .await?;
+    sqlx::query("VACUUM").execute(&pool).await?;
+
+    let store = Store::new_from_pool(pool);
+    for endpoint in ["/gws/", "/geelong/"] {
+        store
+            .add_subscription(TestSubscriptionBuilder::new(String::from(endpoint)).build())
+            .await
+            .expect("Couldn't add subscription");
+    }
+
+    let deliveries: Vec<_> = store
+        .claim_deliveries(0, 0, 60, 10)
+        .await
+        .expect("Couldn't claim deliveries")
+        .into_iter()
+        .map(|delivery| {
+            (
+                delivery.subscription.endpoint,
+                delivery.alert.game_id,
+                delivery.alert.home_score,
+            )
+        })
+        .collect();
+    assert_eq!(
+        deliveries,
+        vec![
+            (String::from("/gws/"), 35740, 80),
+            (String::from("/geelong/"), 35750, 94)
+        ]
+    );
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_matches_subscribers_to_each_notification_kind(pool: SqlitePool) -> sqlx::Result<()> {
+    let store = Store::new_from_pool(pool.clone());
+
+    // a subscriber for each kind on its own, one for everything and one for nothing
+    let mut subscribers = vec![];
+    for kind in DbNotification::ALL {
+        let endpoint = format!("/{}/", kind.name());
+        store
+            .add_subscription(
+                TestSubscriptionBuilder::new(endpoint.clone())
+                    .notification(kind)
+                    .build(),
+            )
+            .await
+            .expect("Couldn't add subscription");
+        subscribers.push((endpoint, Preferences::default().with(kind)));
+    }
+    for (endpoint, notifications) in [
+        ("/everything/", Preferences::all()),
+        ("/nothing/", Preferences::default()),
+    ] {
+        let mut subscription = TestSubscriptionBuilder::new(endpoint.to_string());
+        for kind in Vec::from(notifications) {
+            subscription = subscription.notification(kind);
+        }
+        store
+            .add_subscription(subscription.build())
+            .await
+            .expect("Couldn't add subscription");
+        subscribers.push((endpoint.to_string(), notifications));
+    }
+
+    for kind in DbNotification::ALL {
+        let mut received =
+            alert_recipients(&store, &pool, 35740, Team::Geelong, Team::Hawthorn, kind).await;
+        received.sort();
+
+        let mut expected: Vec<_> = subscribers
+            .iter()
+            .filter(|(_, notifications)| notifications.contains(kind))
+            .map(|(endpoint, _)| endpoint.clone())
+            .collect();
+        expected.sort();
+
+        assert_eq!(received, expected, "{kind:?}");
+        assert_eq!(received.len(), 2, "{kind:?}");
+        assert!(received.contains(&format!("/{}/", kind.name())));
+    }
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_uses_other_preferences_for_games_without_followed_teams(
+    pool: SqlitePool,
+) -> sqlx::Result<()> {
+    let store = Store::new_from_pool(pool.clone());
+
+    // every quarter for my team, but only close games for everyone else
+    store
+        .add_subscription(
+            TestSubscriptionBuilder::new(String::from("/geelong/"))
+                .team(Team::Geelong)
+                .quarter_scores()
+                .final_scores()
+                .other_notification(DbNotification::CloseGame)
+                .build(),
+        )
+        .await
+        .expect("Couldn't add subscription");
+    // other games' preferences don't apply when every game is followed
+    store
+        .add_subscription(
+            TestSubscriptionBuilder::new(String::from("/all/"))
+                .final_scores()
+                .other_notification(DbNotification::CloseGame)
+                .build(),
+        )
+        .await
+        .expect("Couldn't add subscription");
+
+    // a game each, an alert is only recorded once
+    for (game_id, (home_team, away_team, kind, expected)) in (35740..).zip([
+        (
+            Team::Geelong,
+            Team::Hawthorn,
+            DbNotification::EndOfSecondQuarter,
+            vec!["/geelong/"],
+        ),
+        (
+            Team::Hawthorn,
+            Team::Geelong,
+            DbNotification::EndOfGame,
+            vec!["/geelong/", "/all/"],
+        ),
+        (
+            Team::Geelong,
+            Team::Hawthorn,
+            DbNotification::CloseGame,
+            vec![],
+        ),
+        (
+            Team::Sydney,
+            Team::Brisbane,
+            DbNotification::EndOfSecondQuarter,
+            vec![],
+        ),
+        (
+            Team::Sydney,
+            Team::Brisbane,
+            DbNotification::EndOfGame,
+            vec!["/all/"],
+        ),
+        (
+            Team::Sydney,
+            Team::Brisbane,
+            DbNotification::CloseGame,
+            vec!["/geelong/"],
+        ),
+    ]) {
+        let received = alert_recipients(
+            &store,
+            &pool,
+            game_id,
+            home_team.clone(),
+            away_team.clone(),
+            kind,
+        )
+        .await;
+        assert_eq!(received, expected, "{home_team:?} v {away_team:?} {kind:?}");
+    }
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_skips_snoozed_subscriptions_until_the_snooze_lifts(
+    pool: SqlitePool,
+) -> sqlx::Result<()> {
+    let store = Store::new_from_pool(pool.clone());
+
+    for (id, round, is_final) in [(35740, 5, 0), (35750, 6, 0), (35900, 25, 2)] {
+        store
+            .upsert_game(Game {
+                id,
+                round,
+                complete: 0,
+                home_team: Team::Geelong,
+                away_team: Team::Hawthorn,
+                home_score: 0,
+                away_score: 0,
+                timestr: String::new(),
+                year: 2024,
+                date: String::from("2024-04-13 13:45:00"),
+                tz: String::from("+10:00"),
+                is_final,
+                is_grand_final: false,
+            })
+            .await
+            .expect("Couldn't add game");
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    for (endpoint, snooze) in [
+        ("/awake/", None),
+        ("/lapsed/", Some(Snooze::Until(now - 60))),
+        ("/fortnight/", Some(Snooze::Until(now + 14 * 24 * 60 * 60))),
+        (
+            "/next_round/",
+            Some(Snooze::Round {
+                year: 2024,
+                round: 6,
+            }),
+        ),
+        ("/finals/", Some(Snooze::Finals { year: 2024 })),
+    ] {
+        let mut subscription = TestSubscriptionBuilder::new(String::from(endpoint))
+            .final_scores()
+            .build();
+        subscription.set_snooze(snooze);
+        store
+            .add_subscription(subscription)
+            .await
+            .expect("Couldn't add subscription");
+    }
+
+    for (game_id, expected) in [
+        (35740, vec!["/awake/", "/lapsed/"]),
+        (35750, vec!["/awake/", "/lapsed/", "/next_round/"]),
+    ] {
+        let received = alert_recipients(
+            &store,
+            &pool,
+            game_id,
+            Team::Geelong,
+            Team::Hawthorn,
+            DbNotification::EndOfGame,
+        )
+        .await;
+        assert_eq!(received, expected, "{game_id}");
+    }
+
+    // the first alert of the next round lifted snoozes until then
+    for (endpoint, snoozed) in [
+        ("/lapsed/", false),
+        ("/fortnight/", true),
+        ("/next_round/", false),
+        ("/finals/", true),
+    ] {
+        let subscription = store
+            .get_subscription_for_endpoint(endpoint)
+            .await
+            .expect("Couldn't get subscription")
+            .expect("Subscription should exist");
+        assert_eq!(subscription.snooze().is_some(), snoozed, "{endpoint}");
+    }
+
+    let received = alert_recipients(
+        &store,
+        &pool,
+        35900,
+        Team::Geelong,
+        Team::Hawthorn,
+        DbNotification::EndOfGame,
+    )
+    .await;
+    assert_eq!(
+        received,
+        vec!["/awake/", "/lapsed/", "/next_round/", "/finals/"]
+    );
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_limits_alerts_to_the_subscriptions_scope(pool: SqlitePool) -> sqlx::Result<()> {
+    let store = Store::new_from_pool(pool.clone());
+
+    for (id, round, is_final, is_grand_final) in [
+        (35740, 5, 0, false),
+        (35930, 26, 5, false),
+        (35950, 27, 6, true),
+    ] {
+        store
+            .upsert_game(Game {
+                id,
+                round,
+                complete: 0,
+                home_team: Team::Sydney,
+                away_team: Team::Brisbane,
+                home_score: 0,
+                away_score: 0,
+                timestr: String::new(),
+                year: 2024,
+                date: String::from("2024-09-20 19:40:00"),
+                tz: String::from("+10:00"),
+                is_final,
+                is_grand_final,
+            })
+            .await
+            .expect("Couldn't add game");
+    }
+
+    for (endpoint, scope) in [
+        ("/every_game/", Scope::All),
+        ("/finals/", Scope::Finals),
+        ("/grand_final/", Scope::GrandFinal),
+    ] {
+        store
+            .add_subscription(
+                TestSubscriptionBuilder::new(String::from(endpoint))
+                    .final_scores()
+                    .scope(scope)
+                    .build(),
+            )
+            .await
+            .expect("Couldn't add subscription");
+    }
+    // games followed on their own are sent whatever the scope
+    let follow = GameFollow {
+        game_id: 35740,
+        notifications: Preferences::default().with(DbNotification::EndOfGame),
+    };
+    assert!(store
+        .follow_game("/grand_final/", &follow)
+        .await
+        .expect("Couldn't follow game"));
+
+    for (game_id, expected) in [
+        (35740, vec!["/every_game/", "/grand_final/"]),
+        (35930, vec!["/every_game/", "/finals/"]),
+        (35950, vec!["/every_game/", "/finals/", "/grand_final/"]),
+    ] {
+        let received = alert_recipients(
+            &store,
+            &pool,
+            game_id,
+            Team::Sydney,
+            Team::Brisbane,
+            DbNotification::EndOfGame,
+        )
+        .await;
+        assert_eq!(received, expected, "{game_id}");
+    }
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_finds_this_rounds_games_through_finals(pool: SqlitePool) -> sqlx::Result<()> {
+    let store = Store::new_from_pool(pool);
+    let add_game = |id, year, round, complete, is_final| {
+        let store = store.clone();
+        async move {
+            store
+                .upsert_game(Game {
+                    id,
+                    round,
+                    complete,
+                    home_team: Team::Sydney,
+                    away_team: Team::Brisbane,
+                    home_score: 0,
+                    away_score: 0,
+                    timestr: String::new(),
+                    year,
+                    date: String::from("2024-09-20 19:40:00"),
+                    tz: String::from("+10:00"),
+                    is_final,
+                    is_grand_final: is_final == 6,
+                })
+                .await
+                .expect("Couldn't add game");
+        }
+    };
+    let this_round = |store: Store| async move {
+        store
+            .get_this_round_games()
+            .await
+            .expect("Couldn't get games")
+            .into_iter()
+            .map(|game| game.id)
+            .collect::<Vec<_>>()
+    };
+
+    // nothing's started, so the first round to come
+    add_game(35920, 2024, 25, 0, 4).await;
+    add_game(35921, 2024, 25, 0, 4).await;
+    add_game(35930, 2024, 26, 0, 5).await;
+    assert_eq!(this_round(store.clone()).await, vec![35920, 35921]);
+
+    add_game(35930, 2024, 26, 100, 5).await;
+    add_game(35931, 2024, 26, 0, 5).await;
+    assert_eq!(this_round(store.clone()).await, vec![35930, 35931]);
+
+    // next season's Opening Round doesn't take over from the Grand Final until it starts
+    add_game(35950, 2024, 27, 100, 6).await;
+    add_game(36000, 2025, 0, 0, 0).await;
+    assert_eq!(this_round(store.clone()).await, vec![35950]);
+
+    add_game(36000, 2025, 0, 25, 0).await;
+    assert_eq!(this_round(store.clone()).await, vec![36000]);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_follows_a_single_game_until_full_time(pool: SqlitePool) -> sqlx::Result<()> {
+    let store = Store::new_from_pool(pool.clone());
+
+    store
+        .add_subscription(TestSubscriptionBuilder::new(String::from("/showdown/")).build())
+        .await
+        .expect("Couldn't add subscription");
+    let follow = GameFollow {
+        game_id: 35740,
+        notifications: Preferences::default()
+            .with(DbNotification::EndOfThirdQuarter)
+            .with(DbNotification::EndOfGame),
+    };
+    assert!(store
+        .follow_game("/showdown/", &follow)
+        .await
+        .expect("Couldn't follow game"));
+    assert!(!store
+        .follow_game("/unknown/", &follow)
+        .await
+        .expect("Couldn't follow game"));
+    assert_eq!(
+        store
+            .get_game_follows("/showdown/")
+            .await
+            .expect("Couldn't get follows"),
+        vec![follow.clone()]
+    );
+
+    let matching = |game_id, kind| {
+        let store = store.clone();
+        let pool = pool.clone();
+        async move {
+            alert_recipients(
+                &store,
+                &pool,
+                game_id,
+                Team::GreaterWesternSydney,
+                Team::StKilda,
+                kind,
+            )
+            .await
+            .len()
+        }
+    };
+    assert_eq!(matching(35740, DbNotification::EndOfThirdQuarter).await, 1);
+    assert_eq!(matching(35740, DbNotification::CloseGame).await, 0);
+    // same teams, different game
+    assert_eq!(matching(35741, DbNotification::EndOfThirdQuarter).await, 0);
+
+    let now = chrono::Utc::now().timestamp();
+    let full_time = Notification::EndOfGame {
+        home_team: Team::GreaterWesternSydney,
+        away_team: Team::StKilda,
+        home_score: 80,
+        away_score: 79,
+    }
+    .to_record(35740);
+    assert_eq!(
+        store
+            .record_alert(&full_time, now, now, now + 60)
+            .await
+            .expect("Couldn't record alert"),
+        Some(1)
+    );
+    assert_eq!(
+        store
+            .get_game_follows("/showdown/")
+            .await
+            .expect("Couldn't get follows"),
+        vec![]
+    );
+
+    // a game that's over can't be followed
+    store
+        .upsert_game(Game {
+            id: 35740,
+            round: 5,
+            complete: 100,
+            home_team: Team::GreaterWesternSydney,
+            away_team: Team::StKilda,
+            home_score: 80,
+            away_score: 79,
+            timestr: String::from("\"Full Time\""),
+            year: 2024,
+            date: String::from("2024-04-13 13:45:00"),
+            tz: String::from("+10:00"),
+            is_final: 0,
+            is_grand_final: false,
+        })
+        .await
+        .expect("Couldn't add game");
+    assert!(!store
+        .follow_game("/showdown/", &follow)
+        .await
+        .expect("Couldn't follow game"));
+
+    Ok(())
+}
+
 #[sqlx::test]
 async fn it_filters_notifications_by_quarter_full_selection(pool: SqlitePool) -> sqlx::Result<()> {
     let mock_server = SERVER_POOL.get_server();
@@ -349,3 +1155,2741 @@ async fn it_filters_notifications_by_close_selection(pool: SqlitePool) -> sqlx::
 
     Ok(())
 }
+
+#[sqlx::test]
+async fn it_sends_notification_with_json_payload(pool: SqlitePool) -> sqlx::Result<()> {
+    let mock_server = SERVER_POOL.get_server();
+    let processor = create_processor(pool.clone(), mock_server.url_str("/mock_squiggle/"));
+
+    let store = Store::new_from_pool(pool);
+
+    let subscription = TestSubscriptionBuilder::new(mock_server.url_str("/mock_notification_1/"))
+        .final_scores()
+        .json_payload()
+        .build();
+
+    store
+        .add_subscription(subscription)
+        .await
+        .expect("Couldn't add subscription");
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;game=35740",
+        include_str!("example_game.json"),
+    );
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;year=2024;round=5",
+        include_str!("example_round.json"),
+    );
+
+    expect_notification(&mock_server, "/mock_notification_1/");
+
+    processor
+        .process_event(Event::TimeStr(TimeStrEvent {
+            game_id: 35740,
+            timestr: TimeStr::EndOfGame,
+        }))
+        .await
+        .expect("Couldn't process");
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_sets_topic_to_collapse_notifications_per_game(pool: SqlitePool) -> sqlx::Result<()> {
+    let mock_server = SERVER_POOL.get_server();
+    let store = Store::new_from_pool(pool);
+    let client = Client::new("test-user-agent")
+        .expect("Client creation")
+        .with_base_url(mock_server.url_str("/mock_squiggle/"));
+    let policies = Policies {
+        end_of_first_quarter: Policy {
+            collapse: Collapse::Keep,
+            ..Policies::default().end_of_first_quarter
+        },
+        ..Policies::default()
+    };
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY)
+        .expect("Notifier creation")
+        .with_policies(policies);
+    let processor = Processor::new(store.clone(), client, notifier);
+
+    let subscription = TestSubscriptionBuilder::new(mock_server.url_str("/mock_notification_1/"))
+        .quarter_scores()
+        .build();
+
+    store
+        .add_subscription(subscription)
+        .await
+        .expect("Couldn't add subscription");
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;game=35740",
+        include_str!("example_game.json"),
+    );
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;year=2024;round=5",
+        include_str!("example_round.json"),
+    );
+
+    mock_server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/mock_notification_1/"),
+            request::headers(not(contains(key("topic")))),
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    processor
+        .process_event(Event::TimeStr(TimeStrEvent {
+            game_id: 35740,
+            timestr: TimeStr::EndOfFirstQuarter,
+        }))
+        .await
+        .expect("Couldn't process");
+
+    mock_server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/mock_notification_1/"),
+            request::headers(contains(("topic", "game-35740"))),
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    processor
+        .process_event(Event::TimeStr(TimeStrEvent {
+            game_id: 35740,
+            timestr: TimeStr::EndOfSecondQuarter,
+        }))
+        .await
+        .expect("Couldn't process");
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_sets_urgency_and_ttl_from_policy(pool: SqlitePool) -> sqlx::Result<()> {
+    let mock_server = SERVER_POOL.get_server();
+    let processor = create_processor(pool.clone(), mock_server.url_str("/mock_squiggle/"));
+
+    let store = Store::new_from_pool(pool);
+
+    let subscription = TestSubscriptionBuilder::new(mock_server.url_str("/mock_notification_1/"))
+        .final_scores()
+        .build();
+
+    store
+        .add_subscription(subscription)
+        .await
+        .expect("Couldn't add subscription");
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;game=35740",
+        include_str!("example_game.json"),
+    );
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;year=2024;round=5",
+        include_str!("example_round.json"),
+    );
+
+    mock_server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/mock_notification_1/"),
+            request::headers(contains(("urgency", "normal"))),
+            // the TTL counts down from when the alert was queued
+            request::headers(contains(("ttl", matches("^(4319[5-9]|43200)$")))),
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    processor
+        .process_event(Event::TimeStr(TimeStrEvent {
+            game_id: 35740,
+            timestr: TimeStr::EndOfGame,
+        }))
+        .await
+        .expect("Couldn't process");
+
+    Ok(())
+}
+
+async fn outbox_state(pool: &SqlitePool) -> Vec<(u8, u32)> {
+    sqlx::query_as("SELECT status, attempts FROM outbox ORDER BY id")
+        .fetch_all(pool)
+        .await
+        .expect("Couldn't read outbox")
+}
+
+#[sqlx::test]
+async fn it_retries_failed_deliveries_from_outbox(pool: SqlitePool) -> sqlx::Result<()> {
+    let mut mock_server = SERVER_POOL.get_server();
+    let processor = create_processor(pool.clone(), mock_server.url_str("/mock_squiggle/"));
+
+    let store = Store::new_from_pool(pool.clone());
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+
+    let subscription = TestSubscriptionBuilder::new(mock_server.url_str("/mock_notification_1/"))
+        .final_scores()
+        .build();
+
+    store
+        .add_subscription(subscription)
+        .await
+        .expect("Couldn't add subscription");
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;game=35740",
+        include_str!("example_game.json"),
+    );
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;year=2024;round=5",
+        include_str!("example_round.json"),
+    );
+
+    mock_server.expect(
+        Expectation::matching(request::method_path("POST", "/mock_notification_1/"))
+            .respond_with(status_code(500)),
+    );
+
+    processor
+        .process_event(Event::TimeStr(TimeStrEvent {
+            game_id: 35740,
+            timestr: TimeStr::EndOfGame,
+        }))
+        .await
+        .expect("Couldn't process");
+
+    // still pending, waiting for a retry
+    assert_eq!(outbox_state(&pool).await, vec![(0, 1)]);
+    mock_server.verify_and_clear();
+
+    // nothing is due yet
+    notifier.dispatch().await.expect("Couldn't dispatch");
+    assert_eq!(outbox_state(&pool).await, vec![(0, 1)]);
+
+    sqlx::query("UPDATE outbox SET next_attempt_at = 0")
+        .execute(&pool)
+        .await?;
+    expect_notification(&mock_server, "/mock_notification_1/");
+
+    notifier.dispatch().await.expect("Couldn't dispatch");
+    assert_eq!(outbox_state(&pool).await, vec![(1, 2)]);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_expires_deliveries_past_their_ttl(pool: SqlitePool) -> sqlx::Result<()> {
+    let mut mock_server = SERVER_POOL.get_server();
+    let processor = create_processor(pool.clone(), mock_server.url_str("/mock_squiggle/"));
+
+    let store = Store::new_from_pool(pool.clone());
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+
+    let subscription = TestSubscriptionBuilder::new(mock_server.url_str("/mock_notification_1/"))
+        .final_scores()
+        .build();
+
+    store
+        .add_subscription(subscription)
+        .await
+        .expect("Couldn't add subscription");
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;game=35740",
+        include_str!("example_game.json"),
+    );
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;year=2024;round=5",
+        include_str!("example_round.json"),
+    );
+
+    mock_server.expect(
+        Expectation::matching(request::method_path("POST", "/mock_notification_1/"))
+            .respond_with(status_code(503)),
+    );
+
+    processor
+        .process_event(Event::TimeStr(TimeStrEvent {
+            game_id: 35740,
+            timestr: TimeStr::EndOfGame,
+        }))
+        .await
+        .expect("Couldn't process");
+    mock_server.verify_and_clear();
+
+    // the alert goes stale before the push service recovers, so it's never sent
+    sqlx::query("UPDATE outbox SET next_attempt_at = 0, expires_at = 0")
+        .execute(&pool)
+        .await?;
+
+    notifier.dispatch().await.expect("Couldn't dispatch");
+    assert_eq!(outbox_state(&pool).await, vec![(3, 1)]);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_doesnt_retry_deliveries_the_push_service_rejects(pool: SqlitePool) -> sqlx::Result<()> {
+    let mut mock_server = SERVER_POOL.get_server();
+    let store = Store::new_from_pool(pool.clone());
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+    let endpoint = mock_server.url_str("/mock_notification_1/");
+
+    store
+        .add_subscription(
+            TestSubscriptionBuilder::new(endpoint.clone())
+                .final_scores()
+                .build(),
+        )
+        .await
+        .expect("Couldn't add subscription");
+
+    // e.g. too big a payload, which won't get any smaller
+    mock_server.expect(
+        Expectation::matching(request::method_path("POST", "/mock_notification_1/"))
+            .times(1)
+            .respond_with(status_code(413)),
+    );
+    let now = chrono::Utc::now().timestamp();
+    store
+        .record_alert(
+            &Notification::EndOfGame {
+                home_team: Team::Sydney,
+                away_team: Team::Brisbane,
+                home_score: 60,
+                away_score: 120,
+            }
+            .to_record(35740),
+            now,
+            now,
+            now + 3600,
+        )
+        .await
+        .expect("Couldn't record alert");
+    for _ in 0..2 {
+        notifier.dispatch().await.expect("Couldn't dispatch");
+        sqlx::query("UPDATE outbox SET next_attempt_at = 0")
+            .execute(&pool)
+            .await?;
+    }
+    mock_server.verify_and_clear();
+    assert_eq!(outbox_state(&pool).await, vec![(2, 1)]);
+
+    // the subscription itself is fine
+    let subscription = store
+        .get_subscription_for_endpoint(&endpoint)
+        .await
+        .expect("Couldn't get subscription")
+        .expect("Subscription should exist");
+    assert!(subscription.active);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_logs_every_delivery_attempt(pool: SqlitePool) -> sqlx::Result<()> {
+    let mut mock_server = SERVER_POOL.get_server();
+    let processor = create_processor(pool.clone(), mock_server.url_str("/mock_squiggle/"));
+
+    let store = Store::new_from_pool(pool.clone());
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+    let endpoint = mock_server.url_str("/mock_notification_1/");
+
+    let subscription = TestSubscriptionBuilder::new(endpoint.clone())
+        .final_scores()
+        .build();
+
+    store
+        .add_subscription(subscription)
+        .await
+        .expect("Couldn't add subscription");
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;game=35740",
+        include_str!("example_game.json"),
+    );
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;year=2024;round=5",
+        include_str!("example_round.json"),
+    );
+
+    mock_server.expect(
+        Expectation::matching(request::method_path("POST", "/mock_notification_1/"))
+            .respond_with(status_code(500)),
+    );
+
+    processor
+        .process_event(Event::TimeStr(TimeStrEvent {
+            game_id: 35740,
+            timestr: TimeStr::EndOfGame,
+        }))
+        .await
+        .expect("Couldn't process");
+    mock_server.verify_and_clear();
+
+    mock_server.expect(
+        Expectation::matching(request::method_path("POST", "/mock_notification_1/"))
+            .respond_with(status_code(201)),
+    );
+
+    notifier
+        .send_test_notification(&endpoint)
+        .await
+        .expect("Couldn't send test notification");
+
+    let attempts = store
+        .get_delivery_attempts(&endpoint, 10)
+        .await
+        .expect("Couldn't get attempts");
+
+    assert_eq!(attempts.len(), 2);
+
+    // newest first
+    assert_eq!(attempts[0].game_id, None);
+    assert_eq!(attempts[0].notification, None);
+    assert_eq!(attempts[0].status_code, Some(201));
+    assert_eq!(attempts[0].error, None);
+
+    assert_eq!(attempts[1].game_id, Some(35740));
+    assert_eq!(attempts[1].notification, Some(DbNotification::EndOfGame));
+    assert_eq!(attempts[1].status_code, Some(500));
+    assert!(attempts[1].error.is_some());
+    assert!(attempts[1].body.contains("80"));
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_backs_off_push_service_when_rate_limited(pool: SqlitePool) -> sqlx::Result<()> {
+    let mut mock_server = SERVER_POOL.get_server();
+
+    let store = Store::new_from_pool(pool.clone());
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+
+    store
+        .add_subscription(
+            TestSubscriptionBuilder::new(mock_server.url_str("/mock_notification_1/"))
+                .final_scores()
+                .build(),
+        )
+        .await
+        .expect("Couldn't add subscription");
+
+    let now = chrono::Utc::now().timestamp();
+    let record = |game_id| {
+        Notification::EndOfGame {
+            home_team: Team::GreaterWesternSydney,
+            away_team: Team::StKilda,
+            home_score: 80,
+            away_score: 79,
+        }
+        .to_record(game_id)
+    };
+    let outbox = || async {
+        sqlx::query_as::<_, (i64, u32)>("SELECT next_attempt_at, attempts FROM outbox ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .expect("Couldn't read outbox")
+    };
+
+    store
+        .record_alert(&record(35740), now, now, now + 3600)
+        .await
+        .expect("Couldn't record alert");
+
+    mock_server.expect(
+        Expectation::matching(request::method_path("POST", "/mock_notification_1/"))
+            .respond_with(status_code(429).insert_header("Retry-After", "120")),
+    );
+
+    notifier.dispatch().await.expect("Couldn't dispatch");
+    mock_server.verify_and_clear();
+
+    let (retry_at, attempts) = outbox().await[0];
+    assert!((now + 119..=now + 122).contains(&retry_at));
+    assert_eq!(attempts, 1);
+
+    // another subscriber on the same push service isn't sent to until it's ready either
+    store
+        .add_subscription(
+            TestSubscriptionBuilder::new(mock_server.url_str("/mock_notification_2/"))
+                .final_scores()
+                .build(),
+        )
+        .await
+        .expect("Couldn't add subscription");
+    store
+        .record_alert(&record(35741), now, now, now + 3600)
+        .await
+        .expect("Couldn't record alert");
+
+    notifier.dispatch().await.expect("Couldn't dispatch");
+
+    let outbox = outbox().await;
+    assert_eq!(outbox.len(), 3);
+    for (next_attempt_at, attempts) in &outbox[1..] {
+        assert!(*next_attempt_at >= retry_at);
+        assert_eq!(*attempts, 0);
+    }
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_keeps_retry_after_within_bounds(pool: SqlitePool) -> sqlx::Result<()> {
+    let mut mock_server = SERVER_POOL.get_server();
+
+    let store = Store::new_from_pool(pool.clone());
+    store
+        .add_subscription(
+            TestSubscriptionBuilder::new(mock_server.url_str("/mock_notification_1/"))
+                .final_scores()
+                .build(),
+        )
+        .await
+        .expect("Couldn't add subscription");
+
+    // a huge delay first, so its retry isn't picked up again by the next dispatch
+    for (game_id, retry_after, delay) in [
+        (35740, "18446744073709551615", 24 * 60 * 60),
+        (35741, "0", 1),
+    ] {
+        let now = chrono::Utc::now().timestamp();
+        let record = Notification::EndOfGame {
+            home_team: Team::GreaterWesternSydney,
+            away_team: Team::StKilda,
+            home_score: 80,
+            away_score: 79,
+        }
+        .to_record(game_id);
+        store
+            .record_alert(&record, now, now, now + 2 * 24 * 60 * 60)
+            .await
+            .expect("Couldn't record alert");
+
+        mock_server.expect(
+            Expectation::matching(request::method_path("POST", "/mock_notification_1/"))
+                .respond_with(status_code(503).insert_header("Retry-After", retry_after)),
+        );
+        // a fresh throttle, so the push service isn't still blocked from the last one
+        let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+        notifier.dispatch().await.expect("Couldn't dispatch");
+        mock_server.verify_and_clear();
+
+        let (retry_at, attempts): (i64, u32) = sqlx::query_as(
+            "SELECT next_attempt_at, attempts FROM outbox
+             WHERE alert_id = (SELECT alert_id FROM alerts WHERE id = ?)",
+        )
+        .bind(game_id)
+        .fetch_one(&pool)
+        .await?;
+        assert!(
+            (now + delay..=now + delay + 2).contains(&retry_at),
+            "{retry_after}: {retry_at}"
+        );
+        assert_eq!(attempts, 1, "{retry_after}");
+    }
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_bundles_pending_alerts_into_a_digest(pool: SqlitePool) -> sqlx::Result<()> {
+    let mock_server = SERVER_POOL.get_server();
+
+    let store = Store::new_from_pool(pool.clone());
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY)
+        .expect("Notifier creation")
+        .with_fan_out(FanOut {
+            digest_window: 60,
+            ..FanOut::default()
+        });
+    let endpoint = mock_server.url_str("/mock_notification_1/");
+
+    store
+        .add_subscription(
+            TestSubscriptionBuilder::new(endpoint.clone())
+                .quarter_scores()
+                .json_payload()
+                .build(),
+        )
+        .await
+        .expect("Couldn't add subscription");
+
+    let three_quarter_time =
+        |home_team, away_team, home_score, away_score| Notification::EndOfQuarter {
+            quarter: Quarter::Third,
+            home_team,
+            away_team,
+            home_score,
+            away_score,
+        };
+
+    // the second alert is still in its digest window, but goes out with the first
+    let now = chrono::Utc::now().timestamp();
+    store
+        .record_alert(
+            &three_quarter_time(Team::GreaterWesternSydney, Team::StKilda, 60, 61).to_record(35740),
+            now,
+            now,
+            now + 3600,
+        )
+        .await
+        .expect("Couldn't record alert");
+    store
+        .record_alert(
+            &three_quarter_time(Team::Collingwood, Team::Carlton, 80, 71).to_record(35741),
+            now,
+            now + 30,
+            now + 3600,
+        )
+        .await
+        .expect("Couldn't record alert");
+
+    expect_notification(&mock_server, "/mock_notification_1/");
+
+    notifier.dispatch().await.expect("Couldn't dispatch");
+
+    assert_eq!(outbox_state(&pool).await, vec![(1, 1), (1, 1)]);
+
+    let attempts = store
+        .get_delivery_attempts(&endpoint, 10)
+        .await
+        .expect("Couldn't get attempts");
+    assert_eq!(attempts.len(), 1);
+    assert_eq!(attempts[0].title, "2 score updates");
+    assert_eq!(attempts[0].body, "3/4 Time: GWS 60-61 STK · COL 80-71 CARL");
+
+    Ok(())
+}
+
+#[test]
+fn it_labels_each_alert_in_a_mixed_digest() {
+    let alert = digest(
+        &[
+            Notification::EndOfGame {
+                home_team: Team::Geelong,
+                away_team: Team::Hawthorn,
+                home_score: 94,
+                away_score: 82,
+            },
+            Notification::CloseGame {
+                home_team: Team::Sydney,
+                away_team: Team::Brisbane,
+                home_score: 70,
+                away_score: 68,
+                time_str: TimeStr::Other(String::from("Q4 27:12")),
+            },
+        ],
+        Catalog::default().get(Locale::En, Style::Full),
+    );
+
+    assert_eq!(
+        alert.body,
+        "Full Time GEE 94-82 HAW · Close game (Q4 27:12) SYD 70-68 BRI"
+    );
+    assert_eq!(alert.kind, None);
+    assert_eq!(alert.game_id, None);
+}
+
+/// One of each kind of notification, for the golden tests
+fn every_notification() -> [Notification; 5] {
+    let quarter = |quarter| Notification::EndOfQuarter {
+        quarter,
+        home_team: Team::Geelong,
+        away_team: Team::Hawthorn,
+        home_score: 64,
+        away_score: 52,
+    };
+
+    [
+        quarter(Quarter::First),
+        quarter(Quarter::Second),
+        quarter(Quarter::Third),
+        Notification::EndOfGame {
+            home_team: Team::GreaterWesternSydney,
+            away_team: Team::StKilda,
+            home_score: 80,
+            away_score: 79,
+        },
+        Notification::CloseGame {
+            home_team: Team::Sydney,
+            away_team: Team::Brisbane,
+            home_score: 70,
+            away_score: 68,
+            time_str: TimeStr::Other(String::from("Q4 27:12")),
+        },
+    ]
+}
+
+#[test]
+fn it_renders_every_notification_in_every_locale_and_style() {
+    let catalog = Catalog::default();
+    catalog
+        .validate()
+        .expect("Default templates should be valid");
+
+    let golden = [
+        (
+            Locale::En,
+            Style::Full,
+            [
+                ("End of Q1", "End of Q1: Geelong 64 - Hawthorn 52"),
+                ("End of Q2", "End of Q2: Geelong 64 - Hawthorn 52"),
+                ("End of Q3", "End of Q3: Geelong 64 - Hawthorn 52"),
+                ("Full time", "End of game: GWS 80 - St Kilda 79"),
+                (
+                    "Close game",
+                    "Close game (Q4 27:12): Sydney 70 - Brisbane 68",
+                ),
+            ],
+        ),
+        (
+            Locale::En,
+            Style::Compact,
+            [
+                ("1/4 Time", "GEE 64-52 HAW"),
+                ("1/2 Time", "GEE 64-52 HAW"),
+                ("3/4 Time", "GEE 64-52 HAW"),
+                ("Full Time", "GWS 80-79 STK"),
+                ("Close game (Q4 27:12)", "SYD 70-68 BRI"),
+            ],
+        ),
+        (
+            Locale::Es,
+            Style::Full,
+            [
+                ("Fin del Q1", "Fin del Q1: Geelong 64 - Hawthorn 52"),
+                ("Fin del Q2", "Fin del Q2: Geelong 64 - Hawthorn 52"),
+                ("Fin del Q3", "Fin del Q3: Geelong 64 - Hawthorn 52"),
+                (
+                    "Final del partido",
+                    "Final del partido: GWS 80 - St Kilda 79",
+                ),
+                (
+                    "Partido reñido",
+                    "Partido reñido (Q4 27:12): Sydney 70 - Brisbane 68",
+                ),
+            ],
+        ),
+        (
+            Locale::Es,
+            Style::Compact,
+            [
+                ("Primer cuarto", "GEE 64-52 HAW"),
+                ("Medio tiempo", "GEE 64-52 HAW"),
+                ("Tercer cuarto", "GEE 64-52 HAW"),
+                ("Final", "GWS 80-79 STK"),
+                ("Partido reñido (Q4 27:12)", "SYD 70-68 BRI"),
+            ],
+        ),
+    ];
+
+    for (locale, style, expected) in golden {
+        let templates = catalog.get(locale, style);
+        for (notification, (title, body)) in every_notification().iter().zip(expected) {
+            let alert = notification.to_alert(35740, None, templates);
+            assert_eq!(
+                (alert.title.as_str(), alert.body.as_str()),
+                (title, body),
+                "{locale:?} {style:?} {notification:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn it_renders_digests_in_every_locale_and_style() {
+    let catalog = Catalog::default();
+    let [_, _, third_quarter, end_of_game, _] = every_notification();
+    let notifications = [third_quarter, end_of_game];
+
+    let golden = [
+        (
+            Locale::En,
+            Style::Full,
+            "2 score updates",
+            "3/4 Time GEE 64-52 HAW · Full Time GWS 80-79 STK",
+        ),
+        (
+            Locale::En,
+            Style::Compact,
+            "2 updates",
+            "3/4 Time GEE 64-52 HAW · Full Time GWS 80-79 STK",
+        ),
+        (
+            Locale::Es,
+            Style::Full,
+            "2 actualizaciones de marcador",
+            "Tercer cuarto GEE 64-52 HAW · Final GWS 80-79 STK",
+        ),
+        (
+            Locale::Es,
+            Style::Compact,
+            "2 marcadores",
+            "Tercer cuarto GEE 64-52 HAW · Final GWS 80-79 STK",
+        ),
+    ];
+
+    for (locale, style, title, body) in golden {
+        let alert = digest(&notifications, catalog.get(locale, style));
+        assert_eq!(
+            (alert.title.as_str(), alert.body.as_str()),
+            (title, body),
+            "{locale:?} {style:?}"
+        );
+    }
+}
+
+#[test]
+fn it_renders_configured_templates() {
+    let config = serde_json::from_str(
+        r#"{"en": {"compact": {"close_game": {
+            "title": "{{{home_abbr}}} v {away_abbr}",
+            "body": "{margin} points in it ({clock})",
+            "label": "Close"
+        }}}}"#,
+    )
+    .expect("Config should parse");
+    let catalog = Catalog::default()
+        .with_config(config)
+        .expect("Config should be valid");
+
+    let [.., close_game] = every_notification();
+    let alert = close_game.to_alert(35740, None, catalog.get(Locale::En, Style::Compact));
+    assert_eq!(alert.title, "{SYD} v BRI");
+    assert_eq!(alert.body, "2 points in it (Q4 27:12)");
+
+    // other locales and styles keep their defaults
+    let alert = close_game.to_alert(35740, None, catalog.get(Locale::En, Style::Full));
+    assert_eq!(alert.title, "Close game");
+}
+
+#[test]
+fn it_names_finals_in_alert_titles() {
+    assert_eq!(Final::new(0, false), None);
+    assert_eq!(Final::new(5, false), Some(Final::Preliminary));
+    assert_eq!(Final::new(6, true), Some(Final::Grand));
+    assert_eq!(Final::new(1, false), Some(Final::Other));
+
+    let catalog = Catalog::default();
+    let full_time = Notification::EndOfGame {
+        home_team: Team::Sydney,
+        away_team: Team::Brisbane,
+        home_score: 60,
+        away_score: 120,
+    };
+    for (final_round, locale, style, title) in [
+        (
+            Final::Preliminary,
+            Locale::En,
+            Style::Full,
+            "Preliminary Final: Full time",
+        ),
+        (
+            Final::Grand,
+            Locale::En,
+            Style::Compact,
+            "Grand Final: Full Time",
+        ),
+        (
+            Final::Preliminary,
+            Locale::Es,
+            Style::Full,
+            "Final preliminar: Final del partido",
+        ),
+    ] {
+        let alert = full_time.to_alert(35930, Some(final_round), catalog.get(locale, style));
+        assert_eq!(alert.title, title, "{locale:?} {style:?}");
+    }
+
+    let alert = full_time.to_alert(35930, None, catalog.get(Locale::En, Style::Full));
+    assert_eq!(alert.title, "Full time");
+    assert_eq!(alert.body, "End of game: Sydney 60 - Brisbane 120");
+}
+
+#[test]
+fn it_keeps_secrets_out_of_debug_output() {
+    let subscription = Subscription {
+        management_token_hash: Some(String::from("hash_secret")),
+        ..TestSubscriptionBuilder::new(String::from("https://matrix.example.com/!room"))
+            .channel(Channel::Matrix, Some("syt_secret"))
+            .build()
+    };
+
+    let debug = format!("{subscription:?}");
+    assert!(debug.contains("https://matrix.example.com/!room"));
+    for secret in ["syt_secret", "hash_secret", TEST_AUTH] {
+        assert!(!debug.contains(secret), "{secret}");
+    }
+}
+
+#[test]
+fn it_rejects_invalid_templates() {
+    let context = templates::Context::Alert(DbNotification::EndOfFirstQuarter);
+    assert_eq!(
+        Template::new("{home_team} {goals}").validate(context),
+        Err(templates::Error::UnknownPlaceholder(String::from("goals")))
+    );
+    assert_eq!(
+        Template::new("{home_team} ({clock})").validate(context),
+        Err(templates::Error::NotAllowed(String::from("clock")))
+    );
+    assert_eq!(
+        Template::new("{home_team").validate(context),
+        Err(templates::Error::Unclosed)
+    );
+    assert_eq!(
+        Template::new("home_team}").validate(context),
+        Err(templates::Error::Unmatched)
+    );
+    assert_eq!(
+        Template::new("{count} alerts").validate(templates::Context::DigestTitle),
+        Ok(())
+    );
+    // a drawn Grand Final has no premiers
+    assert_eq!(
+        Template::new("{premiers}, {year} premiers").validate(templates::Context::SeasonDrawn),
+        Err(templates::Error::NotAllowed(String::from("premiers")))
+    );
+    assert_eq!(Catalog::default().validate(), Ok(()));
+
+    let config = serde_json::from_str(
+        r#"{"es": {"full": {"end_of_game": {
+            "title": "Final",
+            "body": "Final ({quarter})",
+            "label": "Final"
+        }}}}"#,
+    )
+    .expect("Config should parse");
+    let err = Catalog::default()
+        .with_config(config)
+        .expect_err("Config shouldn't be valid");
+    assert_eq!(
+        err.to_string(),
+        "Invalid end_of_game.body template for Es Full: Placeholder {quarter} can't be used here"
+    );
+}
+
+#[sqlx::test]
+async fn it_sends_alerts_in_subscriber_locale(pool: SqlitePool) -> sqlx::Result<()> {
+    let mock_server = SERVER_POOL.get_server();
+
+    let store = Store::new_from_pool(pool);
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+
+    store
+        .add_subscription(
+            TestSubscriptionBuilder::new(mock_server.url_str("/footy-topic"))
+                .channel(Channel::Ntfy, None)
+                .final_scores()
+                .locale(Locale::Es, Style::Compact)
+                .build(),
+        )
+        .await
+        .expect("Couldn't add subscription");
+
+    mock_server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/footy-topic"),
+            request::headers(contains(("title", "Final"))),
+            request::body("GWS 80-79 STK"),
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    let now = chrono::Utc::now().timestamp();
+    let [.., end_of_game, _] = every_notification();
+    store
+        .record_alert(&end_of_game.to_record(35740), now, now, now + 60)
+        .await
+        .expect("Couldn't record alert");
+    notifier.dispatch().await.expect("Couldn't dispatch");
+
+    Ok(())
+}
+
+#[test]
+fn it_finds_end_of_quiet_hours_in_subscriber_timezone() {
+    let subscription = TestSubscriptionBuilder::new(String::from("/mock_notification_1/"))
+        .timezone("Australia/Sydney")
+        .quiet_hours("22:00", "07:00")
+        .build();
+    let utc = |time: &str| {
+        chrono::DateTime::parse_from_rfc3339(time)
+            .expect("valid time")
+            .with_timezone(&chrono::Utc)
+    };
+
+    // 21:30 in Sydney
+    assert_eq!(subscription.quiet_until(utc("2024-04-13T11:30:00Z")), None);
+    // 22:30 and then 06:00 the next morning
+    assert_eq!(
+        subscription.quiet_until(utc("2024-04-13T12:30:00Z")),
+        Some(utc("2024-04-13T21:00:00Z"))
+    );
+    assert_eq!(
+        subscription.quiet_until(utc("2024-04-13T20:00:00Z")),
+        Some(utc("2024-04-13T21:00:00Z"))
+    );
+    // 07:00
+    assert_eq!(subscription.quiet_until(utc("2024-04-13T21:00:00Z")), None);
+}
+
+#[sqlx::test]
+async fn it_holds_alerts_during_quiet_hours(pool: SqlitePool) -> sqlx::Result<()> {
+    let mock_server = SERVER_POOL.get_server();
+
+    let store = Store::new_from_pool(pool.clone());
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+
+    let now = chrono::Utc::now();
+    let perth = |time: chrono::DateTime<chrono::Utc>| {
+        time.with_timezone(&chrono_tz::Australia::Perth)
+            .format("%H:%M")
+            .to_string()
+    };
+
+    store
+        .add_subscription(
+            TestSubscriptionBuilder::new(mock_server.url_str("/mock_notification_1/"))
+                .final_scores()
+                .close_games()
+                .timezone("Australia/Perth")
+                .quiet_hours(
+                    &perth(now - chrono::TimeDelta::hours(1)),
+                    &perth(now + chrono::TimeDelta::hours(1)),
+                )
+                .build(),
+        )
+        .await
+        .expect("Couldn't add subscription");
+
+    let now = now.timestamp();
+    store
+        .record_alert(
+            &Notification::EndOfGame {
+                home_team: Team::GreaterWesternSydney,
+                away_team: Team::StKilda,
+                home_score: 80,
+                away_score: 79,
+            }
+            .to_record(35740),
+            now,
+            now,
+            now + 4 * 3600,
+        )
+        .await
+        .expect("Couldn't record alert");
+    store
+        .record_alert(
+            &Notification::CloseGame {
+                home_team: Team::WestCoast,
+                away_team: Team::Fremantle,
+                home_score: 70,
+                away_score: 68,
+                time_str: TimeStr::Other(String::from("Q4 27:12")),
+            }
+            .to_record(35741),
+            now,
+            now,
+            now + 300,
+        )
+        .await
+        .expect("Couldn't record alert");
+
+    // nothing is sent until the quiet hours are over, the close game alert is stale by then
+    notifier.dispatch().await.expect("Couldn't dispatch");
+
+    assert_eq!(outbox_state(&pool).await, vec![(0, 0), (3, 1)]);
+    let next_attempt_at: i64 =
+        sqlx::query_scalar("SELECT next_attempt_at FROM outbox ORDER BY id LIMIT 1")
+            .fetch_one(&pool)
+            .await?;
+    assert!((now + 3540..=now + 3600).contains(&next_attempt_at));
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_sends_test_notification_in_subscriber_timezone(pool: SqlitePool) -> sqlx::Result<()> {
+    let mock_server = SERVER_POOL.get_server();
+
+    let store = Store::new_from_pool(pool.clone());
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+    let endpoint = mock_server.url_str("/mock_notification_1/");
+
+    store
+        .add_subscription(
+            TestSubscriptionBuilder::new(endpoint.clone())
+                .timezone("Australia/Perth")
+                .build(),
+        )
+        .await
+        .expect("Couldn't add subscription");
+
+    expect_notification(&mock_server, "/mock_notification_1/");
+
+    notifier
+        .send_test_notification(&endpoint)
+        .await
+        .expect("Couldn't send test notification");
+
+    let attempts = store
+        .get_delivery_attempts(&endpoint, 10)
+        .await
+        .expect("Couldn't get attempts");
+    assert!(attempts[0].body.ends_with("AWST)"));
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_delays_alerts_for_spoiler_free_subscribers(pool: SqlitePool) -> sqlx::Result<()> {
+    let mock_server = SERVER_POOL.get_server();
+
+    let store = Store::new_from_pool(pool.clone());
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+
+    store
+        .add_subscription(
+            TestSubscriptionBuilder::new(mock_server.url_str("/mock_notification_1/"))
+                .final_scores()
+                .spoiler_delay(600)
+                .build(),
+        )
+        .await
+        .expect("Couldn't add subscription");
+
+    let now = chrono::Utc::now().timestamp();
+    store
+        .record_alert(
+            &Notification::EndOfGame {
+                home_team: Team::GreaterWesternSydney,
+                away_team: Team::StKilda,
+                home_score: 80,
+                away_score: 79,
+            }
+            .to_record(35740),
+            now,
+            now,
+            now + 3600,
+        )
+        .await
+        .expect("Couldn't record alert");
+
+    notifier.dispatch().await.expect("Couldn't dispatch");
+
+    let scheduled: Vec<(i64, i64)> =
+        sqlx::query_as("SELECT next_attempt_at, expires_at FROM outbox WHERE status = 0")
+            .fetch_all(&pool)
+            .await?;
+    assert_eq!(scheduled, vec![(now + 600, now + 4200)]);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_holds_alerts_until_released(pool: SqlitePool) -> sqlx::Result<()> {
+    let mut mock_server = SERVER_POOL.get_server();
+
+    let store = Store::new_from_pool(pool.clone());
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+    let endpoint = mock_server.url_str("/mock_notification_1/");
+
+    store
+        .add_subscription(
+            TestSubscriptionBuilder::new(endpoint.clone())
+                .quarter_scores()
+                .final_scores()
+                .hold_alerts()
+                .build(),
+        )
+        .await
+        .expect("Couldn't add subscription");
+
+    let now = chrono::Utc::now().timestamp();
+    for (game_id, notification) in [
+        (
+            35740,
+            Notification::EndOfGame {
+                home_team: Team::GreaterWesternSydney,
+                away_team: Team::StKilda,
+                home_score: 80,
+                away_score: 79,
+            },
+        ),
+        (
+            35741,
+            Notification::EndOfQuarter {
+                quarter: Quarter::First,
+                home_team: Team::Collingwood,
+                away_team: Team::Carlton,
+                home_score: 20,
+                away_score: 31,
+            },
+        ),
+    ] {
+        store
+            .record_alert(&notification.to_record(game_id), now, now, now + 60)
+            .await
+            .expect("Couldn't record alert");
+    }
+
+    // held past when they'd normally expire
+    notifier.dispatch().await.expect("Couldn't dispatch");
+    sqlx::query("UPDATE outbox SET created_at = created_at - 3600, expires_at = expires_at - 3600")
+        .execute(&pool)
+        .await?;
+    notifier.dispatch().await.expect("Couldn't dispatch");
+    assert_eq!(outbox_state(&pool).await, vec![(4, 0), (4, 0)]);
+
+    expect_notification(&mock_server, "/mock_notification_1/");
+    assert_eq!(
+        notifier
+            .release(&endpoint, Some(35741))
+            .await
+            .expect("Couldn't release"),
+        1
+    );
+    assert_eq!(outbox_state(&pool).await, vec![(4, 0), (1, 1)]);
+    mock_server.verify_and_clear();
+
+    expect_notification(&mock_server, "/mock_notification_1/");
+    assert_eq!(
+        notifier
+            .release(&endpoint, None)
+            .await
+            .expect("Couldn't release"),
+        1
+    );
+    assert_eq!(outbox_state(&pool).await, vec![(1, 1), (1, 1)]);
+
+    // but not forever
+    store
+        .record_alert(
+            &Notification::EndOfGame {
+                home_team: Team::Sydney,
+                away_team: Team::Brisbane,
+                home_score: 60,
+                away_score: 120,
+            }
+            .to_record(35742),
+            now,
+            now,
+            now + 60,
+        )
+        .await
+        .expect("Couldn't record alert");
+    sqlx::query("UPDATE outbox SET created_at = created_at - 2 * 24 * 3600 WHERE status = 4")
+        .execute(&pool)
+        .await?;
+    assert_eq!(
+        notifier
+            .release(&endpoint, None)
+            .await
+            .expect("Couldn't release"),
+        0
+    );
+    assert_eq!(outbox_state(&pool).await, vec![(1, 1), (1, 1), (3, 0)]);
+
+    Ok(())
+}
+
+const BENCHMARK_SUBSCRIBERS: u32 = 50_000;
+
+#[sqlx::test]
+#[ignore = "benchmark, run with `cargo test --release -- --ignored fans_out`"]
+async fn it_fans_out_to_many_subscribers(pool: SqlitePool) -> sqlx::Result<()> {
+    let sink = Server::run();
+    let store = Store::new_from_pool(pool.clone());
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY)
+        .expect("Notifier creation")
+        .with_fan_out(FanOut {
+            concurrency: 256,
+            per_domain: 128,
+            ..FanOut::default()
+        });
+
+    sqlx::query(
+        r"
+        WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < ?)
+        INSERT INTO subscriptions (notifications, endpoint, p256dh, auth)
+        SELECT ?, ? || i, ?, ? FROM n
+        ",
+    )
+    .bind(BENCHMARK_SUBSCRIBERS)
+    .bind(Preferences::default().with(DbNotification::EndOfGame))
+    .bind(sink.url_str("/sink/"))
+    .bind(TEST_P256DH)
+    .bind(TEST_AUTH)
+    .execute(&pool)
+    .await?;
+
+    sink.expect(
+        Expectation::matching(all_of![
+            request::method("POST"),
+            request::path(matches("^/sink/")),
+        ])
+        .times(BENCHMARK_SUBSCRIBERS as usize)
+        .respond_with(status_code(201)),
+    );
+
+    let record = Notification::EndOfGame {
+        home_team: Team::GreaterWesternSydney,
+        away_team: Team::StKilda,
+        home_score: 80,
+        away_score: 79,
+    }
+    .to_record(35740);
+
+    let started = std::time::Instant::now();
+    let now = chrono::Utc::now().timestamp();
+    let queued = store
+        .record_alert(&record, now, now, now + 3600)
+        .await
+        .expect("Couldn't record alert");
+    notifier.dispatch().await.expect("Couldn't dispatch");
+    let elapsed = started.elapsed();
+
+    assert_eq!(queued, Some(u64::from(BENCHMARK_SUBSCRIBERS)));
+    let delivered: u32 = sqlx::query_scalar("SELECT COUNT(*) FROM outbox WHERE status = 1")
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(delivered, BENCHMARK_SUBSCRIBERS);
+
+    println!(
+        "Delivered {BENCHMARK_SUBSCRIBERS} alerts in {elapsed:?} ({:.0}/s)",
+        f64::from(BENCHMARK_SUBSCRIBERS) / elapsed.as_secs_f64()
+    );
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_records_each_alert_once(pool: SqlitePool) -> sqlx::Result<()> {
+    let store = Store::new_from_pool(pool.clone());
+
+    for endpoint in ["/mock_notification_1/", "/mock_notification_2/"] {
+        store
+            .add_subscription(
+                TestSubscriptionBuilder::new(endpoint.to_string())
+                    .final_scores()
+                    .build(),
+            )
+            .await
+            .expect("Couldn't add subscription");
+    }
+
+    let record = Notification::EndOfGame {
+        home_team: Team::GreaterWesternSydney,
+        away_team: Team::StKilda,
+        home_score: 80,
+        away_score: 79,
+    }
+    .to_record(35740);
+
+    // two events for the same game racing to send the same alert
+    let (first, second) = tokio::join!(
+        store.record_alert(&record, 0, 0, 60),
+        store.record_alert(&record, 0, 0, 60)
+    );
+    let mut results = vec![
+        first.expect("Couldn't record"),
+        second.expect("Couldn't record"),
+    ];
+    results.sort();
+    assert_eq!(results, vec![None, Some(2)]);
+
+    // and again after a restart
+    assert_eq!(store.record_alert(&record, 0, 0, 60).await.ok(), Some(None));
+    assert_eq!(outbox_state(&pool).await, vec![(0, 0), (0, 0)]);
+
+    Ok(())
+}
+
+#[test]
+fn it_parses_policies_from_configuration() {
+    let policies: Policies = serde_json::from_str(
+        r#"{"close_game": {"collapse": "keep", "urgency": "low", "ttl_seconds": 120}}"#,
+    )
+    .expect("Policies should parse");
+
+    let close_game = policies.get(DbNotification::CloseGame);
+    assert_eq!(close_game.collapse, Collapse::Keep);
+    assert_eq!(close_game.urgency, Urgency::Low);
+    assert_eq!(close_game.ttl_seconds, 120);
+
+    let end_of_game = policies.get(DbNotification::EndOfGame);
+    assert_eq!(end_of_game.urgency, Urgency::Normal);
+    assert_eq!(end_of_game.ttl_seconds, 12 * 60 * 60);
+}
+
+#[test]
+fn it_finds_the_first_bounce_in_the_games_timezone() {
+    let game: squiggle::rest::types::Game = serde_json::from_value(serde_json::json!({
+        "id": 35740, "round": 5, "hteamid": 9, "ateamid": 15, "complete": 0,
+        "winnerteamid": null, "hscore": 0, "ascore": 0, "timestr": null, "year": 2024,
+        "date": "2024-04-13 13:45:00", "tz": "+10:00"
+    }))
+    .expect("Game should parse");
+
+    let starts_at = game_start(&game).expect("Game should have a start");
+    assert_eq!(starts_at.to_rfc3339(), "2024-04-13T03:45:00+00:00");
+}
+
+#[test]
+fn it_builds_versioned_json_payload() {
+    let alert = Notification::EndOfQuarter {
+        quarter: Quarter::Third,
+        home_team: Team::Geelong,
+        away_team: Team::Hawthorn,
+        home_score: 64,
+        away_score: 52,
+    }
+    .to_alert(35740, None, Catalog::default().get(Locale::En, Style::Full));
+
+    let payload = serde_json::to_value(Payload::new(&alert, &[Team::Hawthorn], Collapse::Replace))
+        .expect("Payload should serialize");
+
+    assert_eq!(
+        payload,
+        serde_json::json!({
+            "version": 1,
+            "title": "End of Q3",
+            "body": "End of Q3: Geelong 64 - Hawthorn 52",
+            "tag": "game-35740",
+            "icon": "/team_icons/hawthorn.png",
+            "url": "https://footyalerts.fyi/?game=35740",
+            "game_id": 35740,
+            "kind": "end_of_third_quarter",
+            "scores": {
+                "home_team": "Geelong",
+                "away_team": "Hawthorn",
+                "home_score": 64,
+                "away_score": 52,
+            },
+        })
+    );
+
+    let payload = serde_json::to_value(Payload::new(&alert, &[Team::Carlton], Collapse::Keep))
+        .expect("Payload should serialize");
+
+    assert_eq!(payload["icon"], "/team_icons/geelong.png");
+    assert_eq!(payload["tag"], "game-35740-end_of_third_quarter");
+}
+
+#[sqlx::test]
+async fn it_publishes_to_ntfy_topic(pool: SqlitePool) -> sqlx::Result<()> {
+    let mock_server = SERVER_POOL.get_server();
+    let processor = create_processor(pool.clone(), mock_server.url_str("/mock_squiggle/"));
+
+    let store = Store::new_from_pool(pool);
+
+    let subscription = TestSubscriptionBuilder::new(mock_server.url_str("/footy-topic"))
+        .channel(Channel::Ntfy, Some("tk_secret"))
+        .final_scores()
+        .build();
+
+    store
+        .add_subscription(subscription)
+        .await
+        .expect("Couldn't add subscription");
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;game=35740",
+        include_str!("example_game.json"),
+    );
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;year=2024;round=5",
+        include_str!("example_round.json"),
+    );
+
+    mock_server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/footy-topic"),
+            request::headers(contains(("title", "Full time"))),
+            request::headers(contains(("priority", "4"))),
+            request::headers(contains(("tags", "football,checkered_flag"))),
+            request::headers(contains(("click", "https://footyalerts.fyi/?game=35740"))),
+            request::headers(contains(("authorization", "Bearer tk_secret"))),
+            request::body("End of game: GWS 80 - St Kilda 79"),
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    processor
+        .process_event(Event::TimeStr(TimeStrEvent {
+            game_id: 35740,
+            timestr: TimeStr::EndOfGame,
+        }))
+        .await
+        .expect("Couldn't process");
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_publishes_to_gotify_application(pool: SqlitePool) -> sqlx::Result<()> {
+    let mock_server = SERVER_POOL.get_server();
+    let processor = create_processor(pool.clone(), mock_server.url_str("/mock_squiggle/"));
+
+    let store = Store::new_from_pool(pool);
+
+    let subscription = TestSubscriptionBuilder::new(mock_server.url_str("/message"))
+        .channel(Channel::Gotify, Some("app_token"))
+        .close_games()
+        .build();
+
+    store
+        .add_subscription(subscription)
+        .await
+        .expect("Couldn't add subscription");
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;game=35740",
+        include_str!("example_game.json"),
+    );
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;year=2024;round=5",
+        include_str!("example_round.json"),
+    );
+
+    mock_server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/message"),
+            request::headers(contains(("x-gotify-key", "app_token"))),
+            request::body(json_decoded(eq(serde_json::json!({
+                "title": "Close game",
+                "message": "Close game (EndOfGame): GWS 80 - St Kilda 79",
+                "priority": 8,
+                "extras": {
+                    "client::notification": {
+                        "click": { "url": "https://footyalerts.fyi/?game=35740" }
+                    },
+                    "footyalerts::alert": { "kind": "close_game", "game_id": 35740 },
+                },
+            })))),
+        ])
+        .respond_with(status_code(200)),
+    );
+
+    processor
+        .process_event(Event::Complete(CompleteEvent {
+            game_id: 35740,
+            complete: 99,
+        }))
+        .await
+        .expect("Couldn't process");
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_edits_matrix_message_for_later_alerts(pool: SqlitePool) -> sqlx::Result<()> {
+    let mut mock_server = SERVER_POOL.get_server();
+    let processor = create_processor(pool.clone(), mock_server.url_str("/mock_squiggle/"));
+
+    let store = Store::new_from_pool(pool);
+
+    let endpoint = matrix::room_endpoint(&mock_server.url_str(""), "!room:localhost");
+    let subscription = TestSubscriptionBuilder::new(endpoint)
+        .channel(Channel::Matrix, Some("syt_token"))
+        .quarter_scores()
+        .build();
+
+    store
+        .add_subscription(subscription)
+        .await
+        .expect("Couldn't add subscription");
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;game=35740",
+        include_str!("example_game.json"),
+    );
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;year=2024;round=5",
+        include_str!("example_round.json"),
+    );
+
+    let send_path = "^/_matrix/client/v3/rooms/%21room%3Alocalhost/send/m.room.message/";
+
+    mock_server.expect(
+        Expectation::matching(all_of![
+            request::method("PUT"),
+            request::path(matches(send_path)),
+            request::headers(contains(("authorization", "Bearer syt_token"))),
+            request::body(json_decoded(eq(serde_json::json!({
+                "msgtype": "m.text",
+                "body": "End of Q1\nEnd of Q1: GWS 80 - St Kilda 79\nhttps://footyalerts.fyi/?game=35740",
+                "format": "org.matrix.custom.html",
+                "formatted_body": "<b>End of Q1</b><br>End of Q1: GWS 80 - St Kilda 79 (<a href=\"https://footyalerts.fyi/?game=35740\">live scores</a>)",
+            })))),
+        ])
+        .respond_with(json_encoded(serde_json::json!({ "event_id": "$first" }))),
+    );
+
+    processor
+        .process_event(Event::TimeStr(TimeStrEvent {
+            game_id: 35740,
+            timestr: TimeStr::EndOfFirstQuarter,
+        }))
+        .await
+        .expect("Couldn't process");
+
+    mock_server.verify_and_clear();
+
+    mock_server.expect(
+        Expectation::matching(all_of![
+            request::method("PUT"),
+            request::path(matches(send_path)),
+            request::body(json_decoded(|body: &serde_json::Value| {
+                body["m.relates_to"]
+                    == serde_json::json!({ "rel_type": "m.replace", "event_id": "$first" })
+                    && body["m.new_content"]["body"]
+                        == "End of Q2\nEnd of Q2: GWS 80 - St Kilda 79\nhttps://footyalerts.fyi/?game=35740"
+            })),
+        ])
+        .respond_with(json_encoded(serde_json::json!({ "event_id": "$edit" }))),
+    );
+
+    processor
+        .process_event(Event::TimeStr(TimeStrEvent {
+            game_id: 35740,
+            timestr: TimeStr::EndOfSecondQuarter,
+        }))
+        .await
+        .expect("Couldn't process");
+
+    Ok(())
+}
+
+/// Needs a broker, e.g. `docker run -p 1883:1883 eclipse-mosquitto mosquitto -c
+/// /mosquitto-no-auth.conf`, then `MQTT_TEST_URL=mqtt://localhost:1883 cargo test -- --ignored`
+#[sqlx::test]
+#[ignore = "needs an MQTT broker, run with `MQTT_TEST_URL` set and `-- --ignored`"]
+async fn it_publishes_scores_and_alerts_to_mqtt(pool: SqlitePool) -> sqlx::Result<()> {
+    let broker_url = std::env::var("MQTT_TEST_URL").expect("MQTT_TEST_URL should be set");
+    let mock_server = SERVER_POOL.get_server();
+    let publisher = Publisher::new(&broker_url, None).expect("Publisher creation");
+    let processor = create_processor(pool.clone(), mock_server.url_str("/mock_squiggle/"))
+        .with_publisher(publisher);
+
+    let (listener, mut event_loop) = AsyncClient::new(
+        MqttOptions::parse_url(format!("{broker_url}?client_id=test-listener"))
+            .expect("Listener options"),
+        10,
+    );
+    listener
+        .subscribe("footyalerts/#", QoS::AtLeastOnce)
+        .await
+        .expect("Couldn't subscribe");
+    // wait for the subscription to be acknowledged before publishing anything
+    while !matches!(
+        event_loop.poll().await,
+        Ok(rumqttc::Event::Incoming(Packet::SubAck(_)))
+    ) {}
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;game=35740",
+        include_str!("example_game.json"),
+    );
+
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;year=2024;round=5",
+        include_str!("example_round.json"),
+    );
+
+    processor
+        .process_event(Event::Score(ScoreEvent {
+            game_id: 35740,
+            score_type: String::from("goal"),
+            team: Some(Team::StKilda),
+            complete: 50,
+            score: Score {
+                home_score: 40,
+                away_score: 46,
+            },
+            timestr: TimeStr::EndOfSecondQuarter,
+        }))
+        .await
+        .expect("Couldn't process");
+
+    let mut topics = vec![];
+    while topics.len() < 6 {
+        let event = tokio::time::timeout(Duration::from_secs(5), event_loop.poll())
+            .await
+            .expect("Timed out waiting for messages")
+            .expect("Listener connection");
+
+        if let rumqttc::Event::Incoming(Packet::Publish(publish)) = event {
+            if publish.topic == "footyalerts/game/35740/score" {
+                let game: serde_json::Value =
+                    serde_json::from_slice(&publish.payload).expect("Score payload");
+                assert_eq!(game["home_score"], 40);
+                assert_eq!(game["away_score"], 46);
+            }
+            topics.push(publish.topic);
+        }
+    }
+
+    topics.sort();
+    assert_eq!(
+        topics,
+        [
+            "footyalerts/game/35740/alert",
+            "footyalerts/game/35740/event",
+            "footyalerts/game/35740/score",
+            "footyalerts/team/gws/alert",
+            "footyalerts/team/stkilda/alert",
+            "footyalerts/team/stkilda/score",
+        ]
+    );
+
+    Ok(())
+}
+
+/// Sends a request to the API, returning the status and the JSON body if there is one
+async fn api_request(
+    router: &Router,
+    method: &str,
+    uri: &str,
+    headers: &[(&str, &str)],
+    body: Option<Value>,
+) -> (StatusCode, Option<Value>) {
+    let request = headers
+        .iter()
+        .fold(Request::builder(), |request, (name, value)| {
+            request.header(*name, *value)
+        })
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+        .expect("Request should build");
+    let response = router
+        .clone()
+        .oneshot(request)
+        .await
+        .expect("Router shouldn't fail");
+
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("Couldn't read body");
+    (status, serde_json::from_slice(&bytes).ok())
+}
+
+const API_ENDPOINT: &str = "https://push.example.com/send/abc";
+
+fn api_subscription_uri(path: &str) -> String {
+    format!("{path}?endpoint={}", urlencoding::encode(API_ENDPOINT))
+}
+
+/// Subscribes, returning the management token
+async fn api_subscribe(router: &Router) -> String {
+    let (status, body) = api_request(
+        router,
+        "POST",
+        "/subscription",
+        &[],
+        Some(json!({
+            "teams": [7],
+            "notifications": ["end_of_game"],
+            "web_push": {
+                "endpoint": API_ENDPOINT,
+                "keys": {"p256dh": TEST_P256DH, "auth": TEST_AUTH},
+            },
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    body.expect("Body should be JSON")["management_token"]
+        .as_str()
+        .expect("Token should be a string")
+        .to_string()
+}
+
+#[sqlx::test]
+async fn it_validates_new_subscriptions(pool: SqlitePool) -> sqlx::Result<()> {
+    let store = Store::new_from_pool(pool);
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+    let router = create_router(store.clone(), notifier);
+
+    for (field, value) in [
+        ("spoiler_delay", json!(7 * 24 * 60 * 60)),
+        ("teams", json!([7, 7])),
+        ("payload_version", json!(99)),
+        ("quiet_hours", json!({"start": "22:00", "end": "22:00"})),
+    ] {
+        let mut subscription = json!({
+            "web_push": {
+                "endpoint": API_ENDPOINT,
+                "keys": {"p256dh": TEST_P256DH, "auth": TEST_AUTH},
+            },
+        });
+        subscription[field] = value;
+        let (status, _) =
+            api_request(&router, "POST", "/subscription", &[], Some(subscription)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{field}");
+    }
+    assert!(store
+        .get_subscription_for_endpoint(API_ENDPOINT)
+        .await
+        .expect("Couldn't get subscription")
+        .is_none());
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_partially_updates_a_subscription(pool: SqlitePool) -> sqlx::Result<()> {
+    let store = Store::new_from_pool(pool);
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+    let router = create_router(store, notifier);
+    let bearer = format!("Bearer {}", api_subscribe(&router).await);
+    let auth = [("authorization", bearer.as_str())];
+
+    let uri = api_subscription_uri("/subscription");
+    let (status, body) = api_request(
+        &router,
+        "PATCH",
+        &uri,
+        &auth,
+        Some(json!({
+            "notifications": ["end_of_third_quarter", "close_game"],
+            "quiet_hours": {"start": "22:00", "end": "07:00"},
+            "scope": "finals",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let body = body.expect("Body should be JSON");
+    assert_eq!(
+        body["notifications"],
+        json!(["end_of_third_quarter", "close_game"])
+    );
+    assert_eq!(body["scope"], json!("finals"));
+    assert_eq!(
+        body["quiet_hours"],
+        json!({"start": "22:00", "end": "07:00"})
+    );
+    // untouched
+    assert_eq!(body["teams"], json!(["Geelong"]));
+    assert_eq!(body["channel"], json!("web_push"));
+
+    let (status, body) = api_request(
+        &router,
+        "PATCH",
+        &uri,
+        &auth,
+        Some(json!({"quiet_hours": null})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let body = body.expect("Body should be JSON");
+    assert_eq!(body["quiet_hours"], Value::Null);
+    assert_eq!(
+        body["notifications"],
+        json!(["end_of_third_quarter", "close_game"])
+    );
+
+    let (status, body) = api_request(&router, "GET", &uri, &auth, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body.expect("Body should be JSON")["quiet_hours"],
+        Value::Null
+    );
+
+    for invalid in [
+        json!({"spoiler_delay": 7 * 24 * 60 * 60}),
+        json!({"quiet_hours": {"start": "22:00", "end": "22:00"}}),
+        json!({"teams": [7, 7]}),
+        json!({"payload_version": 99}),
+        json!({"notifications": ["goal"]}),
+        json!({"quiet_hours": {"start": "25:00", "end": "07:00"}}),
+        json!({"colour": "blue"}),
+        json!({"scope": "preseason"}),
+    ] {
+        let (status, _) = api_request(&router, "PATCH", &uri, &auth, Some(invalid.clone())).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{invalid}");
+    }
+
+    let (status, _) = api_request(
+        &router,
+        "PATCH",
+        "/subscription?endpoint=https%3A%2F%2Fpush.example.com%2Fmissing",
+        &auth,
+        Some(json!({"hold_alerts": true})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_unsubscribes_and_purges_subscriptions(pool: SqlitePool) -> sqlx::Result<()> {
+    let store = Store::new_from_pool(pool.clone());
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+    let router = create_router(store.clone(), notifier);
+    let bearer = format!("Bearer {}", api_subscribe(&router).await);
+    let auth = [("authorization", bearer.as_str())];
+
+    let now = chrono::Utc::now().timestamp();
+    let full_time = |game_id| {
+        Notification::EndOfGame {
+            home_team: Team::Geelong,
+            away_team: Team::Hawthorn,
+            home_score: 94,
+            away_score: 82,
+        }
+        .to_record(game_id)
+    };
+    store
+        .record_alert(&full_time(35740), now, now + 60, now + 3600)
+        .await
+        .expect("Couldn't record alert");
+    assert_eq!(outbox_state(&pool).await, vec![(0, 0)]);
+
+    // unsubscribing keeps the preferences, but nothing more is sent
+    let uri = api_subscription_uri("/subscription");
+    let (status, _) = api_request(&router, "DELETE", &uri, &auth, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(outbox_state(&pool).await, vec![(2, 0)]);
+
+    let (status, body) = api_request(&router, "GET", &uri, &auth, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let body = body.expect("Body should be JSON");
+    assert_eq!(body["active"], json!(false));
+    assert_eq!(body["notifications"], json!(["end_of_game"]));
+
+    let queued = store
+        .record_alert(&full_time(35741), now, now, now + 3600)
+        .await
+        .expect("Couldn't record alert");
+    assert_eq!(queued, Some(0));
+
+    // partial updates don't resubscribe
+    let (status, body) =
+        api_request(&router, "PATCH", &uri, &auth, Some(json!({"locale": "es"}))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.expect("Body should be JSON")["active"], json!(false));
+
+    let (status, _) =
+        api_request(&router, "DELETE", &format!("{uri}&purge=true"), &auth, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = api_request(&router, "GET", &uri, &auth, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(outbox_state(&pool).await, vec![]);
+
+    let (status, _) = api_request(&router, "DELETE", &uri, &auth, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_requires_ownership_to_manage_subscriptions(pool: SqlitePool) -> sqlx::Result<()> {
+    let store = Store::new_from_pool(pool);
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+    let router = create_router(store.clone(), notifier);
+    let token = api_subscribe(&router).await;
+
+    let uri = api_subscription_uri("/subscription");
+    let patch = Some(json!({"hold_alerts": true}));
+    for headers in [
+        vec![],
+        vec![("authorization", "Bearer wrong")],
+        vec![("x-subscription-auth", "wrong")],
+    ] {
+        for (method, uri, body) in [
+            ("GET", uri.clone(), None),
+            ("PATCH", uri.clone(), patch.clone()),
+            ("DELETE", uri.clone(), None),
+            ("POST", api_subscription_uri("/test_notification"), None),
+            (
+                "GET",
+                api_subscription_uri("/subscription/notifications"),
+                None,
+            ),
+        ] {
+            let (status, _) = api_request(&router, method, &uri, &headers, body).await;
+            assert_eq!(
+                status,
+                StatusCode::UNAUTHORIZED,
+                "{method} {uri} {headers:?}"
+            );
+        }
+    }
+    let stored = store
+        .get_subscription_for_endpoint(API_ENDPOINT)
+        .await
+        .expect("Couldn't get subscription")
+        .expect("Subscription should exist");
+    assert!(stored.active);
+    assert!(!stored.hold_alerts);
+
+    // only the hash is stored
+    assert_ne!(
+        stored.management_token_hash.as_deref(),
+        Some(token.as_str())
+    );
+
+    // the browser can use its own web push keys
+    let (status, _) = api_request(
+        &router,
+        "GET",
+        &uri,
+        &[("x-subscription-auth", TEST_AUTH)],
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // someone else can't take over the endpoint with their own keys
+    let (status, _) = api_request(
+        &router,
+        "POST",
+        "/subscription",
+        &[],
+        Some(json!({
+            "teams": [1],
+            "notifications": ["close_game"],
+            "web_push": {
+                "endpoint": API_ENDPOINT,
+                "keys": {"p256dh": TEST_P256DH, "auth": "c29tZW9uZSBlbHNl"},
+            },
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // subscribing again with the same keys gives a new token, and the old one stops working
+    let new_token = api_subscribe(&router).await;
+    assert_ne!(new_token, token);
+    let old = format!("Bearer {token}");
+    let (status, _) = api_request(&router, "GET", &uri, &[("authorization", &old)], None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let new = format!("Bearer {new_token}");
+    let (status, _) = api_request(&router, "GET", &uri, &[("authorization", &new)], None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // saving with the token keeps it, so other devices holding it aren't logged out
+    let (status, body) = api_request(
+        &router,
+        "POST",
+        "/subscription",
+        &[("authorization", &new)],
+        Some(json!({
+            "teams": [7, 10],
+            "notifications": ["end_of_game"],
+            "web_push": {
+                "endpoint": API_ENDPOINT,
+                "keys": {"p256dh": TEST_P256DH, "auth": TEST_AUTH},
+            },
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body.expect("Body should be JSON"), json!({}));
+    let (status, body) = api_request(&router, "GET", &uri, &[("authorization", &new)], None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body.expect("Body should be JSON")["teams"],
+        json!(["Geelong", "Hawthorn"])
+    );
+
+    Ok(())
+}
+
+/// Responds OK to everything, passing on the body of each request
+struct Capture(std::sync::mpsc::Sender<String>);
+
+impl httptest::responders::Responder for Capture {
+    fn respond<'a>(
+        &mut self,
+        request: &'a axum::http::Request<axum::body::Bytes>,
+    ) -> std::pin::Pin<
+        Box<dyn std::future::Future<Output = axum::http::Response<axum::body::Bytes>> + Send + 'a>,
+    > {
+        self.0
+            .send(String::from_utf8_lossy(request.body()).into_owned())
+            .expect("Couldn't pass on request body");
+        Box::pin(async { axum::http::Response::new(axum::body::Bytes::new()) })
+    }
+}
+
+#[sqlx::test]
+async fn it_requires_a_claim_code_for_subscriptions_from_before_tokens(
+    pool: SqlitePool,
+) -> sqlx::Result<()> {
+    let mock_server = SERVER_POOL.get_server();
+    let store = Store::new_from_pool(pool);
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+    let router = create_router(store.clone(), notifier);
+    let topic_url = mock_server.url_str("/footy-topic");
+
+    // no management token, web push keys or ntfy token to prove ownership with
+    store
+        .add_subscription(Subscription {
+            p256dh: String::new(),
+            auth: String::new(),
+            ..TestSubscriptionBuilder::new(topic_url.clone())
+                .channel(Channel::Ntfy, None)
+                .final_scores()
+                .build()
+        })
+        .await
+        .expect("Couldn't add subscription");
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    mock_server.expect(
+        Expectation::matching(request::method_path("POST", "/footy-topic"))
+            .times(1)
+            .respond_with(Capture(sender)),
+    );
+    let takeover = Some(json!({
+        "teams": [1],
+        "notifications": ["close_game"],
+        "ntfy": {"topic_url": topic_url},
+    }));
+
+    // the code is only sent once while it's still usable
+    for _ in 0..2 {
+        let (status, _) =
+            api_request(&router, "POST", "/subscription", &[], takeover.clone()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+    let message = receiver.try_recv().expect("Claim code should be sent");
+    let code = message
+        .rsplit(' ')
+        .next()
+        .expect("Message should end with the code");
+
+    let (status, _) = api_request(
+        &router,
+        "POST",
+        "/subscription",
+        &[("x-claim-code", "wrong")],
+        takeover.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let stored = store
+        .get_subscription_for_endpoint(&topic_url)
+        .await
+        .expect("Couldn't get subscription")
+        .expect("Subscription should exist");
+    assert!(stored.teams.is_empty());
+
+    let (status, _) = api_request(
+        &router,
+        "POST",
+        "/subscription",
+        &[("x-claim-code", code)],
+        takeover.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let stored = store
+        .get_subscription_for_endpoint(&topic_url)
+        .await
+        .expect("Couldn't get subscription")
+        .expect("Subscription should exist");
+    assert_eq!(stored.teams, vec![Team::Adelaide]);
+    assert!(stored.management_token_hash.is_some());
+
+    // the code is used up, and now there's a token it isn't needed
+    let (status, _) = api_request(
+        &router,
+        "POST",
+        "/subscription",
+        &[("x-claim-code", code)],
+        takeover,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_lets_channel_subscriptions_be_replaced_with_their_token(
+    pool: SqlitePool,
+) -> sqlx::Result<()> {
+    let mock_server = SERVER_POOL.get_server();
+    let store = Store::new_from_pool(pool);
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+    let router = create_router(store.clone(), notifier);
+    let topic_url = mock_server.url_str("/footy-topic");
+
+    store
+        .add_subscription(Subscription {
+            p256dh: String::new(),
+            auth: String::new(),
+            ..TestSubscriptionBuilder::new(topic_url.clone())
+                .channel(Channel::Ntfy, Some("tk_secret"))
+                .build()
+        })
+        .await
+        .expect("Couldn't add subscription");
+
+    // without the right token it's claimed like any other
+    mock_server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/footy-topic"),
+            request::headers(contains(("authorization", "Bearer tk_secret"))),
+        ])
+        .times(1)
+        .respond_with(status_code(200)),
+    );
+    for (token, expected) in [
+        ("tk_wrong", StatusCode::FORBIDDEN),
+        ("tk_secret", StatusCode::CREATED),
+    ] {
+        let (status, _) = api_request(
+            &router,
+            "POST",
+            "/subscription",
+            &[],
+            Some(json!({"ntfy": {"topic_url": topic_url, "token": token}})),
+        )
+        .await;
+        assert_eq!(status, expected, "{token}");
+    }
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_reports_test_notifications_that_fail(pool: SqlitePool) -> sqlx::Result<()> {
+    let mock_server = SERVER_POOL.get_server();
+    let store = Store::new_from_pool(pool);
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+    let router = create_router(store, notifier);
+    let topic_url = mock_server.url_str("/footy-topic");
+
+    let (status, body) = api_request(
+        &router,
+        "POST",
+        "/subscription",
+        &[],
+        Some(json!({"ntfy": {"topic_url": topic_url}})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let bearer = format!(
+        "Bearer {}",
+        body.expect("Body should be JSON")["management_token"]
+            .as_str()
+            .expect("Token should be a string")
+    );
+    let uri = format!(
+        "/test_notification?endpoint={}",
+        urlencoding::encode(&topic_url)
+    );
+
+    for (response, expected) in [
+        (500, StatusCode::BAD_GATEWAY),
+        (404, StatusCode::BAD_REQUEST),
+    ] {
+        mock_server.expect(
+            Expectation::matching(request::method_path("POST", "/footy-topic"))
+                .respond_with(status_code(response)),
+        );
+        let (status, _) =
+            api_request(&router, "POST", &uri, &[("authorization", &bearer)], None).await;
+        assert_eq!(status, expected, "{response}");
+    }
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_moves_subscriptions_to_their_new_endpoint(pool: SqlitePool) -> sqlx::Result<()> {
+    let store = Store::new_from_pool(pool.clone());
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+    let router = create_router(store.clone(), notifier);
+    let bearer = format!("Bearer {}", api_subscribe(&router).await);
+    let auth = [("authorization", bearer.as_str())];
+
+    let follow = GameFollow {
+        game_id: 35741,
+        notifications: [DbNotification::CloseGame].into_iter().collect(),
+    };
+    assert!(store
+        .follow_game(API_ENDPOINT, &follow)
+        .await
+        .expect("Couldn't follow game"));
+    let now = chrono::Utc::now().timestamp();
+    let alert = Notification::EndOfGame {
+        home_team: Team::Geelong,
+        away_team: Team::Hawthorn,
+        home_score: 94,
+        away_score: 82,
+    }
+    .to_record(35740);
+    store
+        .record_alert(&alert, now, now + 60, now + 3600)
+        .await
+        .expect("Couldn't record alert");
+    store
+        .record_delivery_attempt(&DeliveryAttempt {
+            endpoint: API_ENDPOINT.to_string(),
+            game_id: None,
+            notification: None,
+            title: "Test".to_string(),
+            body: "Test notification".to_string(),
+            attempted_at: now,
+            status_code: Some(201),
+            latency_ms: 10,
+            error: None,
+        })
+        .await
+        .expect("Couldn't record attempt");
+
+    let new_endpoint = "https://push.example.com/send/def";
+    let change = |old_auth: &str| {
+        json!({
+            "old": {
+                "endpoint": API_ENDPOINT,
+                "keys": {"p256dh": TEST_P256DH, "auth": old_auth},
+            },
+            "new": {
+                "endpoint": new_endpoint,
+                "keys": {"p256dh": TEST_P256DH, "auth": "bmV3IGF1dGgga2V5"},
+            },
+        })
+    };
+
+    let (status, _) = api_request(
+        &router,
+        "POST",
+        "/subscription/change",
+        &[],
+        Some(change("d3Jvbmc")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // the service worker only has the old keys
+    for _ in 0..2 {
+        let (status, body) = api_request(
+            &router,
+            "POST",
+            "/subscription/change",
+            &[],
+            Some(change(TEST_AUTH)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let body = body.expect("Body should be JSON");
+        assert_eq!(body["teams"], json!(["Geelong"]));
+        assert_eq!(body["notifications"], json!(["end_of_game"]));
+        assert_eq!(body["active"], json!(true));
+    }
+
+    let old = store
+        .get_subscription_for_endpoint(API_ENDPOINT)
+        .await
+        .expect("Couldn't get subscription")
+        .expect("Old subscription should be kept");
+    assert!(!old.active);
+    assert_eq!(old.superseded_by.as_deref(), Some(new_endpoint));
+
+    let new = store
+        .get_subscription_for_endpoint(new_endpoint)
+        .await
+        .expect("Couldn't get subscription")
+        .expect("New subscription should exist");
+    assert_eq!(new.auth, "bmV3IGF1dGgga2V5");
+    assert_eq!(
+        store
+            .get_game_follows(new_endpoint)
+            .await
+            .expect("Couldn't get follows"),
+        vec![follow]
+    );
+    assert_eq!(
+        store
+            .get_delivery_attempts(new_endpoint, 10)
+            .await
+            .expect("Couldn't get attempts")
+            .len(),
+        1
+    );
+    let pending: Vec<(String,)> = sqlx::query_as("SELECT endpoint FROM outbox")
+        .fetch_all(&pool)
+        .await?;
+    assert_eq!(pending, vec![(new_endpoint.to_string(),)]);
+
+    let recipients = alert_recipients(
+        &store,
+        &pool,
+        35742,
+        Team::Geelong,
+        Team::Hawthorn,
+        DbNotification::EndOfGame,
+    )
+    .await;
+    assert_eq!(recipients, vec![new_endpoint]);
+
+    // the old subscription isn't counted twice
+    let stats = serde_json::to_value(store.get_stats().await.expect("Couldn't get stats"))
+        .expect("Stats should serialize");
+    assert_eq!(stats["total_subscriptions"], json!(1));
+    assert_eq!(stats["domains"], json!({"push.example.com": 1}));
+
+    // the management token carries over
+    let uri = format!(
+        "/subscription?endpoint={}",
+        urlencoding::encode(new_endpoint)
+    );
+    let (status, _) = api_request(&router, "GET", &uri, &auth, None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_wont_move_subscriptions_onto_someone_elses(pool: SqlitePool) -> sqlx::Result<()> {
+    let store = Store::new_from_pool(pool);
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+    let router = create_router(store.clone(), notifier);
+    let bearer = format!("Bearer {}", api_subscribe(&router).await);
+
+    let other_endpoint = "https://push.example.com/send/xyz";
+    let other_auth = "b3RoZXIgYXV0aCBrZXk";
+    let (status, _) = api_request(
+        &router,
+        "POST",
+        "/subscription",
+        &[],
+        Some(json!({
+            "teams": [4],
+            "web_push": {
+                "endpoint": other_endpoint,
+                "keys": {"p256dh": TEST_P256DH, "auth": other_auth},
+            },
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let change = |new_auth: &str| {
+        json!({
+            "old": {
+                "endpoint": other_endpoint,
+                "keys": {"p256dh": TEST_P256DH, "auth": other_auth},
+            },
+            "new": {
+                "endpoint": API_ENDPOINT,
+                "keys": {"p256dh": TEST_P256DH, "auth": new_auth},
+            },
+        })
+    };
+    let (status, _) = api_request(
+        &router,
+        "POST",
+        "/subscription/change",
+        &[],
+        Some(change(other_auth)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let existing = store
+        .get_subscription_for_endpoint(API_ENDPOINT)
+        .await
+        .expect("Couldn't get subscription")
+        .expect("Subscription should exist");
+    assert_eq!(existing.teams, vec![Team::Geelong]);
+    assert_eq!(existing.auth, TEST_AUTH);
+    let other = store
+        .get_subscription_for_endpoint(other_endpoint)
+        .await
+        .expect("Couldn't get subscription")
+        .expect("Subscription should exist");
+    assert!(other.active);
+    assert_eq!(other.superseded_by, None);
+
+    // unless they show it's theirs too
+    let (status, body) = api_request(
+        &router,
+        "POST",
+        "/subscription/change",
+        &[("authorization", &bearer)],
+        Some(change(other_auth)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body.expect("Body should be JSON")["teams"],
+        json!(["Collingwood"])
+    );
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_snoozes_through_the_api(pool: SqlitePool) -> sqlx::Result<()> {
+    let store = Store::new_from_pool(pool);
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+    let router = create_router(store.clone(), notifier);
+    let bearer = format!("Bearer {}", api_subscribe(&router).await);
+    let auth = [("authorization", bearer.as_str())];
+
+    let uri = api_subscription_uri("/subscription/snooze");
+    for invalid in [
+        json!("next_round"),
+        json!({"weeks": 0}),
+        json!({"until": 1_000_000}),
+    ] {
+        let (status, _) = api_request(&router, "POST", &uri, &auth, Some(invalid.clone())).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{invalid}");
+    }
+    let (status, _) = api_request(&router, "POST", &uri, &[], Some(json!({"weeks": 2}))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, body) = api_request(&router, "POST", &uri, &auth, Some(json!({"weeks": 2}))).await;
+    assert_eq!(status, StatusCode::OK);
+    let until = body.expect("Body should be JSON")["snooze"]["until"]
+        .as_i64()
+        .expect("Should snooze until a time");
+    let fortnight = chrono::Utc::now().timestamp() + 14 * 24 * 60 * 60;
+    assert!((fortnight - 60..=fortnight).contains(&until));
+
+    // saving preferences again keeps the snooze
+    let bearer = format!("Bearer {}", api_subscribe(&router).await);
+    let auth = [("authorization", bearer.as_str())];
+    let (status, body) = api_request(
+        &router,
+        "GET",
+        &api_subscription_uri("/subscription"),
+        &auth,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body.expect("Body should be JSON")["snooze"],
+        json!({"until": until})
+    );
+
+    let (status, body) = api_request(&router, "POST", &uri, &auth, Some(json!("finals"))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body.expect("Body should be JSON")["snooze"]["finals"]["year"],
+        json!(chrono::Datelike::year(&chrono::Utc::now()))
+    );
+
+    let (status, _) = api_request(&router, "DELETE", &uri, &auth, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let subscription = store
+        .get_subscription_for_endpoint(API_ENDPOINT)
+        .await
+        .expect("Couldn't get subscription")
+        .expect("Subscription should exist");
+    assert_eq!(subscription.snooze(), None);
+
+    Ok(())
+}
+
+#[sqlx::test]
+async fn it_sends_season_pushes_from_the_fixture(pool: SqlitePool) -> sqlx::Result<()> {
+    let mut mock_server = SERVER_POOL.get_server();
+    let store = Store::new_from_pool(pool.clone());
+    let notifier = Notifier::new(store.clone(), TEST_PRIVATE_KEY).expect("Notifier creation");
+    let client = Client::new("test-user-agent")
+        .expect("Client creation")
+        .with_base_url(mock_server.url_str("/mock_squiggle/"));
+    let season = Season::new(store.clone(), client, notifier);
+
+    store
+        .upsert_game(Game {
+            id: 35950,
+            round: 27,
+            complete: 100,
+            home_team: Team::Sydney,
+            away_team: Team::Brisbane,
+            home_score: 60,
+            away_score: 120,
+            timestr: String::from("\"Full Time\""),
+            year: 2024,
+            date: String::from("2024-09-28 14:30:00"),
+            tz: String::from("+10:00"),
+            is_final: 6,
+            is_grand_final: true,
+        })
+        .await
+        .expect("Couldn't add game");
+    for path in ["/keep/", "/gone/", "/flaky/"] {
+        store
+            .add_subscription(TestSubscriptionBuilder::new(mock_server.url_str(path)).build())
+            .await
+            .expect("Couldn't add subscription");
+    }
+    let active = |pool: SqlitePool| async move {
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM subscriptions WHERE active = 1")
+            .fetch_one(&pool)
+            .await
+            .expect("Couldn't count subscriptions")
+    };
+    let last_sent = |store: Store, path: String| async move {
+        store
+            .get_delivery_attempts(&path, 1)
+            .await
+            .expect("Couldn't get attempts")
+            .pop()
+            .expect("Should have been sent something")
+            .body
+    };
+
+    // after the Grand Final, but not in the middle of the night, and next season's fixture
+    // waits for it so the push isn't skipped
+    let night = "2024-09-28T15:00:00Z".parse().expect("Valid date");
+    season.tick(night).await.expect("Couldn't tick");
+    mock_server.verify_and_clear();
+    assert_eq!(
+        store.get_latest_year().await.expect("Couldn't get year"),
+        Some(2024)
+    );
+
+    let evening = "2024-09-29T08:00:00Z".parse().expect("Valid date");
+    for (path, status) in [("/keep/", 201), ("/gone/", 410), ("/flaky/", 201)] {
+        mock_server.expect(
+            Expectation::matching(request::method_path("POST", path))
+                .respond_with(status_code(status)),
+        );
+    }
+    expect_squiggle_response(
+        &mock_server,
+        "q=games;year=2025;round=0",
+        r#"{"games": [
+            {"id": 36000, "round": 0, "hteamid": 16, "ateamid": 10, "complete": 0,
+             "winnerteamid": null, "hscore": 0, "ascore": 0, "timestr": null, "year": 2025,
+             "date": "2025-03-07 19:40:00", "tz": "+11:00"},
+            {"id": 36001, "round": 0, "hteamid": 9, "ateamid": 4, "complete": 0,
+             "winnerteamid": null, "hscore": 0, "ascore": 0, "timestr": null, "year": 2025,
+             "date": "2025-03-08 16:15:00", "tz": "+11:00"}
+        ]}"#,
+    );
+    season.tick(evening).await.expect("Couldn't tick");
+    // only once
+    season
+        .tick(evening + chrono::TimeDelta::hours(1))
+        .await
+        .expect("Couldn't tick");
+    mock_server.verify_and_clear();
+    assert_eq!(
+        last_sent(store.clone(), mock_server.url_str("/keep/")).await,
+        "Congratulations Brisbane, 2024 premiers! That's the season, see you next year."
+    );
+    assert_eq!(active(pool.clone()).await, 2);
+
+    // too early to remind anyone
+    season
+        .tick("2025-02-20T01:00:00Z".parse().expect("Valid date"))
+        .await
+        .expect("Couldn't tick");
+
+    // a push service of its own, so backing off from it doesn't hold up the others
+    let busy = mock_server
+        .url_str("/busy/")
+        .replace("127.0.0.1", "localhost");
+    store
+        .add_subscription(
+            TestSubscriptionBuilder::new(busy.clone())
+                .channel(Channel::Ntfy, None)
+                .build(),
+        )
+        .await
+        .expect("Couldn't add subscription");
+    store
+        .add_subscription(
+            TestSubscriptionBuilder::new(mock_server.url_str("/es/"))
+                .locale(Locale::Es, Style::Full)
+                .build(),
+        )
+        .await
+        .expect("Couldn't add subscription");
+
+    let before_opening_round = "2025-03-05T01:00:00Z".parse().expect("Valid date");
+    for (path, status) in [("/keep/", 201), ("/flaky/", 500), ("/es/", 201)] {
+        mock_server.expect(
+            Expectation::matching(request::method_path("POST", path))
+                .respond_with(status_code(status)),
+        );
+    }
+    mock_server.expect(
+        Expectation::matching(request::method_path("POST", "/busy/"))
+            .respond_with(status_code(429).insert_header("Retry-After", "1")),
+    );
+    season
+        .tick(before_opening_round)
+        .await
+        .expect("Couldn't tick");
+    mock_server.verify_and_clear();
+    assert_eq!(
+        last_sent(store.clone(), mock_server.url_str("/keep/")).await,
+        "The 2025 season starts Friday 7 March. Opening Round: SYD v HAW · GWS v COL"
+    );
+    assert_eq!(
+        last_sent(store.clone(), mock_server.url_str("/es/")).await,
+        "La temporada 2025 empieza el viernes 7 de marzo. Ronda inaugural: SYD v HAW · GWS v \
+         COL"
+    );
+
+    // only whoever was held up gets it next time, once their push service is ready
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    mock_server.expect(
+        Expectation::matching(request::method_path("POST", "/busy/"))
+            .respond_with(status_code(200)),
+    );
+    for hours in 1..=2 {
+        season
+            .tick(before_opening_round + chrono::TimeDelta::hours(hours))
+            .await
+            .expect("Couldn't tick");
+    }
+    mock_server.verify_and_clear();
+    assert_eq!(
+        last_sent(store.clone(), busy).await,
+        "The 2025 season starts Friday 7 March. Opening Round: SYD v HAW · GWS v COL"
+    );
+    // failing once isn't enough to be dropped
+    assert_eq!(active(pool.clone()).await, 4);
+
+    Ok(())
+}
@@ -1,19 +1,59 @@
+import { PUBLIC_API_BASE_URL } from '$env/static/public';
+
 self.addEventListener('activate', async (event) => {
 	console.log('HELLO FROM SW');
 });
 
+// Payloads are versioned JSON, older backends sent the notification text as is
+function parsePayload(data) {
+	try {
+		const payload = data.json();
+		if (payload && payload.version >= 1) {
+			return payload;
+		}
+	} catch (error) {
+		// not JSON, fall through to plain text
+	}
+	return { title: 'Footy Alerts', body: data.text(), url: 'https://footyalerts.fyi' };
+}
+
 self.addEventListener('push', (event) => {
 	console.log('Waiting for notification');
+	const payload = parsePayload(event.data);
 	const options = {
-		body: event.data.text(),
-		icon: '/apple-touch-icon.png',
-		badge: '/notification-badge.png'
+		body: payload.body,
+		icon: payload.icon ?? '/apple-touch-icon.png',
+		badge: '/notification-badge.png',
+		tag: payload.tag,
+		// still alert when replacing an earlier notification for the same game
+		renotify: payload.tag !== undefined,
+		data: { url: payload.url, gameId: payload.game_id }
 	};
 	console.log('Notification');
-	event.waitUntil(self.registration.showNotification('Footy Alerts', options));
+	event.waitUntil(self.registration.showNotification(payload.title, options));
 });
 
 self.addEventListener('notificationclick', function (event) {
 	event.notification.close();
-	event.waitUntil(clients.openWindow('https://footyalerts.fyi'));
+	const url = event.notification.data?.url ?? 'https://footyalerts.fyi';
+	event.waitUntil(clients.openWindow(url));
+});
+
+// The browser moved our push subscription, move the preferences along with it
+self.addEventListener('pushsubscriptionchange', (event) => {
+	const old = event.oldSubscription;
+	if (!old) {
+		return;
+	}
+	event.waitUntil(
+		(async () => {
+			const subscription =
+				event.newSubscription ?? (await self.registration.pushManager.subscribe(old.options));
+			await fetch(`${PUBLIC_API_BASE_URL}/subscription/change`, {
+				method: 'POST',
+				headers: { 'Content-Type': 'application/json' },
+				body: JSON.stringify({ old: old.toJSON(), new: subscription.toJSON() })
+			});
+		})()
+	);
 });